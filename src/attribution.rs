@@ -0,0 +1,456 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use uom::si::energy::joule;
+use uom::si::f64::Energy;
+
+use crate::cgroup::{read_cpu_usage, read_io_bytes, read_memory_peak};
+use crate::procfs::{
+    clock_ticks_per_second, collect_descendants, read_system_cpu_times, ProcessScan,
+};
+use crate::{MeasurementConfig, MeasurementError};
+
+/// How the measured system energy is attributed to the workload
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub enum AttributionMode {
+    /// Report the system energy only
+    #[default]
+    Off,
+    /// Apportion the system energy by the CPU time of the
+    /// [`WorkloadProcesses`]
+    ProcessTree,
+    /// Apportion the system energy by the CPU usage of the cgroup v2 at
+    /// the given path, e.g. one made with [`Cgroup::create`](crate::Cgroup)
+    Cgroup(PathBuf),
+}
+
+impl Display for AttributionMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttributionMode::Off => write!(f, "off"),
+            AttributionMode::ProcessTree => write!(f, "process-tree"),
+            AttributionMode::Cgroup(path) => write!(f, "cgroup {}", path.display()),
+        }
+    }
+}
+
+impl FromStr for AttributionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(AttributionMode::Off),
+            "process-tree" => Ok(AttributionMode::ProcessTree),
+            _ => Err(format!("Unknown attribution mode: {}", s)),
+        }
+    }
+}
+
+/// Which processes make up the workload when it is followed by process
+/// tree
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WorkloadProcesses {
+    /// This process and its descendants, for workloads run in-process
+    #[default]
+    Current,
+    /// Only the descendants of this process, for workloads it spawns, so
+    /// that the sampling itself is left out
+    Children,
+}
+
+/// The share of the system energy attributed to the workload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attribution {
+    /// Energy measured for the whole system
+    pub system_energy: Energy,
+    /// Part of `system_energy` attributed to the workload
+    pub attributed_energy: Energy,
+    /// Workload CPU time as a fraction of the busy CPU time of the system
+    pub cpu_share: f64,
+    /// CPU time of the workload, summed over all CPUs
+    pub cpu_time: Duration,
+    /// Peak memory usage of the cgroup in bytes
+    #[serde(default)]
+    pub memory_peak_bytes: Option<u64>,
+    /// Bytes read from block devices by the cgroup
+    #[serde(default)]
+    pub io_read_bytes: Option<u64>,
+    /// Bytes written to block devices by the cgroup
+    #[serde(default)]
+    pub io_write_bytes: Option<u64>,
+}
+
+impl Display for Attribution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.2} J of {:.2} J ({:.1}% of CPU time)",
+            self.attributed_energy.get::<joule>(),
+            self.system_energy.get::<joule>(),
+            self.cpu_share * 100.0
+        )
+    }
+}
+
+/// Follows the CPU time of the workload for [`AttributionMode`]
+pub(crate) enum WorkloadTracker {
+    ProcessTree(ProcessTreeTracker),
+    Cgroup(CgroupTracker),
+}
+
+impl WorkloadTracker {
+    /// Starts tracking the workload of `config.attribution`, if any
+    pub(crate) fn open(config: &MeasurementConfig) -> Result<Option<Self>, MeasurementError> {
+        let root = &config.fs_root;
+        Ok(match &config.attribution {
+            AttributionMode::Off => None,
+            AttributionMode::ProcessTree => Some(WorkloadTracker::ProcessTree(
+                ProcessTreeTracker::new(root, std::process::id(), config.workload_processes)?,
+            )),
+            AttributionMode::Cgroup(path) => {
+                Some(WorkloadTracker::Cgroup(CgroupTracker::new(root, path)?))
+            }
+        })
+    }
+
+    /// Updates the CPU time of the workload, reading processes from `scan`
+    pub(crate) fn sample(&mut self, scan: &ProcessScan) -> Result<(), MeasurementError> {
+        match self {
+            WorkloadTracker::ProcessTree(tracker) => tracker.sample(scan),
+            WorkloadTracker::Cgroup(tracker) => tracker.sample(),
+        }
+    }
+
+    /// Attributes `system_energy` by the workload's share of busy CPU time
+    pub(crate) fn attribute(&self, system_energy: Energy) -> Attribution {
+        match self {
+            WorkloadTracker::ProcessTree(tracker) => tracker.attribute(system_energy),
+            WorkloadTracker::Cgroup(tracker) => tracker.attribute(system_energy),
+        }
+    }
+}
+
+/// Splits `system_energy` by the share of `cpu_time` in `busy_time`
+fn attribute_cpu_time(
+    system_energy: Energy,
+    cpu_time: Duration,
+    busy_time: Duration,
+) -> Attribution {
+    let cpu_share = if !busy_time.is_zero() {
+        (cpu_time.as_secs_f64() / busy_time.as_secs_f64()).min(1.0)
+    } else {
+        0.0
+    };
+    Attribution {
+        system_energy,
+        attributed_energy: system_energy * cpu_share,
+        cpu_share,
+        cpu_time,
+        memory_peak_bytes: None,
+        io_read_bytes: None,
+        io_write_bytes: None,
+    }
+}
+
+/// Follows the CPU usage of a cgroup v2
+///
+/// Unlike following a process tree this also covers processes that were
+/// orphaned or daemonized, as they stay in the cgroup.
+pub(crate) struct CgroupTracker {
+    root: PathBuf,
+    path: PathBuf,
+    start_usage: u64,
+    last_usage: u64,
+    start_busy: u64,
+    last_busy: u64,
+    start_io: Option<(u64, u64)>,
+    ticks_per_second: f64,
+}
+
+impl CgroupTracker {
+    /// Starts tracking the cgroup at `path`, reading `proc` below `root`
+    pub(crate) fn new(
+        root: impl AsRef<Path>,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, MeasurementError> {
+        let root = root.as_ref().to_path_buf();
+        let path = path.as_ref().to_path_buf();
+        let usage = read_cpu_usage(&path)?;
+        let busy = read_system_cpu_times(&root)?.busy;
+        Ok(Self {
+            start_io: read_io_bytes(&path),
+            root,
+            path,
+            start_usage: usage,
+            last_usage: usage,
+            start_busy: busy,
+            last_busy: busy,
+            ticks_per_second: clock_ticks_per_second(),
+        })
+    }
+
+    /// Updates the CPU usage of the cgroup
+    pub(crate) fn sample(&mut self) -> Result<(), MeasurementError> {
+        self.last_usage = read_cpu_usage(&self.path)?;
+        self.last_busy = read_system_cpu_times(&self.root)?.busy;
+        Ok(())
+    }
+
+    /// Attributes `system_energy` by the cgroup's share of busy CPU time
+    pub(crate) fn attribute(&self, system_energy: Energy) -> Attribution {
+        let mut attribution = attribute_cpu_time(
+            system_energy,
+            // The counters restart if the cgroup is recreated
+            Duration::from_micros(self.last_usage.saturating_sub(self.start_usage)),
+            Duration::from_secs_f64(
+                self.last_busy.saturating_sub(self.start_busy) as f64 / self.ticks_per_second,
+            ),
+        );
+        attribution.memory_peak_bytes = read_memory_peak(&self.path);
+        if let (Some(start), Some(end)) = (self.start_io, read_io_bytes(&self.path)) {
+            attribution.io_read_bytes = Some(end.0.saturating_sub(start.0));
+            attribution.io_write_bytes = Some(end.1.saturating_sub(start.1));
+        }
+        attribution
+    }
+}
+
+/// A process last seen in the tree
+#[derive(Debug, Clone, Copy)]
+struct TrackedProcess {
+    ppid: u32,
+    /// Own CPU time plus that of reaped children, in clock ticks
+    ticks: u64,
+}
+
+/// Follows the CPU time of a process and all of its descendants
+///
+/// CPU time of exited descendants shows up in the `cutime`/`cstime` of the
+/// parent that reaps them. Descendants that were orphaned and reaped outside
+/// the tree keep the time they had when last seen.
+pub(crate) struct ProcessTreeTracker {
+    root: PathBuf,
+    pid: u32,
+    /// Whether the own CPU time of `pid` is counted
+    processes: WorkloadProcesses,
+    members: HashMap<u32, TrackedProcess>,
+    /// Ticks of processes that left the tree without being reaped by it
+    lost: u64,
+    start_ticks: u64,
+    last_ticks: u64,
+    start_busy: u64,
+    last_busy: u64,
+    ticks_per_second: f64,
+}
+
+impl ProcessTreeTracker {
+    /// Starts tracking `pid`, or only its descendants, using `proc` below
+    /// `root`
+    pub(crate) fn new(
+        root: impl AsRef<Path>,
+        pid: u32,
+        processes: WorkloadProcesses,
+    ) -> Result<Self, MeasurementError> {
+        let mut tracker = Self {
+            root: root.as_ref().to_path_buf(),
+            pid,
+            processes,
+            members: HashMap::new(),
+            lost: 0,
+            start_ticks: 0,
+            last_ticks: 0,
+            start_busy: 0,
+            last_busy: 0,
+            ticks_per_second: clock_ticks_per_second(),
+        };
+        tracker.sample(&ProcessScan::default())?;
+        tracker.start_ticks = tracker.last_ticks;
+        tracker.start_busy = tracker.last_busy;
+        Ok(tracker)
+    }
+
+    /// Updates the tree and its CPU time from the processes in `scan`
+    pub(crate) fn sample(&mut self, scan: &ProcessScan) -> Result<(), MeasurementError> {
+        let busy = read_system_cpu_times(&self.root)?.busy;
+        let alive = scan.processes(&self.root)?;
+        if !alive.contains_key(&self.pid) {
+            return Err(MeasurementError::InvalidMeasurement(format!(
+                "process {} is not in /proc",
+                self.pid
+            )));
+        }
+
+        // Known members stay in the tree when they are orphaned
+        let mut members: Vec<u32> = self
+            .members
+            .keys()
+            .copied()
+            .chain([self.pid])
+            .filter(|pid| alive.contains_key(pid))
+            .collect();
+        collect_descendants(alive, &mut members);
+
+        for (pid, process) in &self.members {
+            // Children of a member are reaped by it, so their time moves to
+            // its cutime; anything else was reaped outside the tree
+            if !alive.contains_key(pid) && !self.members.contains_key(&process.ppid) {
+                self.lost += process.ticks;
+            }
+        }
+
+        self.members = members
+            .into_iter()
+            .map(|pid| {
+                let stat = alive[&pid];
+                // Without its own time the root only stands in for the
+                // children it reaped
+                let own = match self.processes {
+                    WorkloadProcesses::Children if pid == self.pid => 0,
+                    _ => stat.cpu_time,
+                };
+                let process = TrackedProcess {
+                    ppid: stat.ppid,
+                    ticks: own + stat.children_cpu_time,
+                };
+                (pid, process)
+            })
+            .collect();
+        let ticks = self.members.values().map(|p| p.ticks).sum::<u64>() + self.lost;
+        // A child that exits between reading it and its parent briefly
+        // disappears from the sum
+        self.last_ticks = self.last_ticks.max(ticks);
+        self.last_busy = self.last_busy.max(busy);
+        Ok(())
+    }
+
+    /// CPU time of the tree as of the last sample, in clock ticks
+    pub(crate) fn ticks(&self) -> u64 {
+        self.last_ticks
+    }
+
+    /// Attributes `system_energy` by the tree's share of busy CPU time
+    pub(crate) fn attribute(&self, system_energy: Energy) -> Attribution {
+        let seconds = |ticks: u64| Duration::from_secs_f64(ticks as f64 / self.ticks_per_second);
+        attribute_cpu_time(
+            system_energy,
+            seconds(self.last_ticks - self.start_ticks),
+            seconds(self.last_busy - self.start_busy),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::FakeRoot;
+    use std::fs;
+
+    fn stat(pid: u32, ppid: u32, cpu: u64, children: u64) -> String {
+        format!("{pid} (worker) S {ppid} 1 1 0 -1 0 0 0 0 0 {cpu} 0 {children} 0 20 0 1 0 1 0 0")
+    }
+
+    #[test]
+    fn test_process_tree_share() {
+        let root = FakeRoot::new("attribution-tree");
+        root.file("proc/stat", "cpu  1000 0 0 5000 0 0 0 0\n")
+            .file("proc/100/stat", &stat(100, 1, 10, 0))
+            .file("proc/200/stat", &stat(200, 100, 20, 0))
+            .file("proc/300/stat", &stat(300, 200, 30, 0))
+            .file("proc/400/stat", &stat(400, 1, 500, 0));
+        let mut tracker =
+            ProcessTreeTracker::new(root.path(), 100, WorkloadProcesses::Current).unwrap();
+        assert_eq!(tracker.members.len(), 3);
+
+        // 300 exits and is reaped by 200, and 500 is spawned and orphaned
+        fs::remove_dir_all(root.path().join("proc/300")).unwrap();
+        root.file("proc/stat", "cpu  1100 0 0 5100 0 0 0 0\n")
+            .file("proc/200/stat", &stat(200, 100, 25, 40))
+            .file("proc/500/stat", &stat(500, 200, 5, 0))
+            .file("proc/400/stat", &stat(400, 1, 550, 0));
+        tracker.sample(&ProcessScan::default()).unwrap();
+        root.file("proc/500/stat", &stat(500, 1, 15, 0));
+        tracker.sample(&ProcessScan::default()).unwrap();
+        assert!(tracker.members.contains_key(&500));
+
+        // The orphan is reaped by init, keeping the time it was last seen with
+        fs::remove_dir_all(root.path().join("proc/500")).unwrap();
+        root.file("proc/stat", "cpu  1120 0 0 5200 0 0 0 0\n");
+        tracker.sample(&ProcessScan::default()).unwrap();
+
+        // 10 + 25 + 40 + 15 now against 10 + 20 + 30 at the start
+        let attribution = tracker.attribute(Energy::new::<joule>(100.0));
+        assert_eq!(attribution.cpu_share, 0.25);
+        assert_eq!(attribution.attributed_energy.get::<joule>(), 25.0);
+    }
+
+    #[test]
+    fn test_children_share() {
+        let root = FakeRoot::new("attribution-children");
+        root.file("proc/stat", "cpu  1000 0 0 5000 0 0 0 0\n")
+            .file("proc/100/stat", &stat(100, 1, 10, 0))
+            .file("proc/200/stat", &stat(200, 100, 20, 0));
+        let mut tracker =
+            ProcessTreeTracker::new(root.path(), 100, WorkloadProcesses::Children).unwrap();
+
+        // The sampling process spends 40 ticks of its own while its child
+        // runs for 30 and is reaped
+        root.file("proc/stat", "cpu  1100 0 0 5100 0 0 0 0\n")
+            .file("proc/100/stat", &stat(100, 1, 30, 0))
+            .file("proc/200/stat", &stat(200, 100, 40, 0));
+        tracker.sample(&ProcessScan::default()).unwrap();
+        fs::remove_dir_all(root.path().join("proc/200")).unwrap();
+        root.file("proc/100/stat", &stat(100, 1, 50, 50));
+        tracker.sample(&ProcessScan::default()).unwrap();
+
+        let attribution = tracker.attribute(Energy::new::<joule>(100.0));
+        assert_eq!(attribution.cpu_share, 0.3);
+    }
+
+    #[test]
+    fn test_cgroup_share() {
+        let root = FakeRoot::new("attribution-cgroup");
+        let cgroup = "sys/fs/cgroup/ci.service/energy-1";
+        root.file("proc/stat", "cpu  1000 0 0 5000 0 0 0 0\n")
+            .file(&format!("{cgroup}/cpu.stat"), "usage_usec 0\n")
+            .file(&format!("{cgroup}/io.stat"), "8:0 rbytes=100 wbytes=0\n");
+        let mut tracker = CgroupTracker::new(root.path(), root.path().join(cgroup)).unwrap();
+
+        let ticks = clock_ticks_per_second() as u64;
+        root.file(
+            "proc/stat",
+            &format!("cpu  {} 0 0 5000 0 0 0 0\n", 1000 + 4 * ticks),
+        )
+        .file(&format!("{cgroup}/cpu.stat"), "usage_usec 1000000\n")
+        .file(&format!("{cgroup}/io.stat"), "8:0 rbytes=4196 wbytes=512\n")
+        .file(&format!("{cgroup}/memory.peak"), "1048576\n");
+        tracker.sample().unwrap();
+
+        let attribution = tracker.attribute(Energy::new::<joule>(100.0));
+        assert_eq!(attribution.cpu_share, 0.25);
+        assert_eq!(attribution.cpu_time, Duration::from_secs(1));
+        assert_eq!(attribution.memory_peak_bytes, Some(1048576));
+        assert_eq!(attribution.io_read_bytes, Some(4096));
+        assert_eq!(attribution.io_write_bytes, Some(512));
+
+        // A recreated cgroup starts counting from zero again
+        root.file(&format!("{cgroup}/cpu.stat"), "usage_usec 10\n");
+        tracker.start_usage = 500;
+        tracker.sample().unwrap();
+        let attribution = tracker.attribute(Energy::new::<joule>(100.0));
+        assert_eq!(attribution.cpu_share, 0.0);
+        assert_eq!(attribution.cpu_time, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_attribution_mode_parse() {
+        assert_eq!("off".parse(), Ok(AttributionMode::Off));
+        assert_eq!("process-tree".parse(), Ok(AttributionMode::ProcessTree));
+        assert!("cgroup".parse::<AttributionMode>().is_err());
+    }
+}
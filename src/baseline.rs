@@ -0,0 +1,136 @@
+use std::{fmt::Display, thread, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use uom::si::f64::{Energy, Power};
+use uom::si::{energy::joule, power::watt};
+
+use crate::meter::{MeterError, MeterSlot, Sampler};
+use crate::PowerMeter;
+
+/// When the idle power of a [`Baseline`] was measured
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum BaselineMethod {
+    /// Before the workload
+    Before,
+    /// Before and after the workload, averaging the two
+    BeforeAndAfter,
+}
+
+impl Display for BaselineMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BaselineMethod::Before => write!(f, "before"),
+            BaselineMethod::BeforeAndAfter => write!(f, "before and after"),
+        }
+    }
+}
+
+/// Idle power measured around the workload and the energy net of it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Baseline {
+    /// When the idle power was measured
+    pub method: BaselineMethod,
+    /// Length of each idle phase
+    pub period: Duration,
+    /// Idle power measured before the workload
+    pub before_power: Power,
+    /// Idle power measured after the workload
+    pub after_power: Option<Power>,
+    /// Idle power the baseline energy is based on
+    pub power: Power,
+    /// Idle power over the duration of the workload
+    pub energy: Energy,
+    /// Gross energy minus the baseline energy, never below zero
+    pub net_energy: Energy,
+}
+
+impl Baseline {
+    /// Computes the baseline of a workload that took `duration` and
+    /// consumed `gross_energy`
+    pub(crate) fn new(
+        period: Duration,
+        before_power: Power,
+        after_power: Option<Power>,
+        duration: Duration,
+        gross_energy: Energy,
+    ) -> Self {
+        let (method, power) = match after_power {
+            Some(after_power) => (
+                BaselineMethod::BeforeAndAfter,
+                (before_power + after_power) / 2.0,
+            ),
+            None => (BaselineMethod::Before, before_power),
+        };
+        let energy = Energy::new::<joule>(power.get::<watt>() * duration.as_secs_f64());
+        let net_energy = Energy::new::<joule>((gross_energy - energy).get::<joule>().max(0.0));
+        Self {
+            method,
+            period,
+            before_power,
+            after_power,
+            power,
+            energy,
+            net_energy,
+        }
+    }
+}
+
+/// Measures the average power of `meter` while this thread sleeps for
+/// `period`, handing the meter back
+pub(crate) fn measure_idle(
+    meter: Box<dyn PowerMeter>,
+    interval: Duration,
+    period: Duration,
+) -> Result<(Power, Box<dyn PowerMeter>), MeterError> {
+    let handle = Sampler::start(meter)?.spawn(interval, None, None);
+    thread::sleep(period);
+    let (measurement, meter) = handle.stop()?.finish();
+    Ok((measurement.average_power, meter))
+}
+
+/// Measures the average power of `meter` while the current task sleeps
+/// for `period`, handing the meter back, or putting it into `home` if the
+/// future is dropped
+pub(crate) async fn measure_idle_async(
+    meter: Box<dyn PowerMeter>,
+    interval: Duration,
+    period: Duration,
+    home: Option<MeterSlot>,
+) -> Result<(Power, Box<dyn PowerMeter>), MeterError> {
+    let task = Sampler::start(meter)?.spawn_task(interval, None, None, home);
+    tokio::time::sleep(period).await;
+    let (measurement, meter) = task.stop().await?.finish();
+    Ok((measurement.average_power, meter))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_baseline_net_energy() {
+        let watts = Power::new::<watt>;
+        let baseline = Baseline::new(
+            Duration::from_secs(1),
+            watts(4.0),
+            Some(watts(6.0)),
+            Duration::from_secs(10),
+            Energy::new::<joule>(80.0),
+        );
+        assert_eq!(baseline.method, BaselineMethod::BeforeAndAfter);
+        assert_eq!(baseline.power.get::<watt>(), 5.0);
+        assert_eq!(baseline.energy.get::<joule>(), 50.0);
+        assert_eq!(baseline.net_energy.get::<joule>(), 30.0);
+
+        // Noise may put the idle draw above a tiny workload
+        let baseline = Baseline::new(
+            Duration::from_secs(1),
+            watts(4.0),
+            None,
+            Duration::from_secs(10),
+            Energy::new::<joule>(35.0),
+        );
+        assert_eq!(baseline.method, BaselineMethod::Before);
+        assert_eq!(baseline.net_energy.get::<joule>(), 0.0);
+    }
+}
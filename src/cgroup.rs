@@ -0,0 +1,195 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    path::{Path, PathBuf},
+};
+
+use crate::MeasurementError;
+
+/// A cgroup v2 created below the cgroup of the current process
+///
+/// Creating it requires that the current cgroup is delegated to the user,
+/// as systemd does for user sessions and CI runners with `Delegate=yes`.
+/// The processes go into a `workload` leaf of the created cgroup, so that
+/// controllers can be enabled for it without moving anything else. The
+/// directories are removed on drop, which only succeeds once all of their
+/// processes have exited.
+#[derive(Debug)]
+pub struct Cgroup {
+    /// The created cgroup, whose only child is `path`
+    group: PathBuf,
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Creates a child cgroup `name` of the current process's cgroup with
+    /// a `workload` leaf, reading `proc` and `sys/fs/cgroup` below `root`
+    pub fn create(root: impl AsRef<Path>, name: &str) -> Result<Self, MeasurementError> {
+        let root = root.as_ref();
+        let membership = fs::read_to_string(root.join("proc/self/cgroup"))?;
+        // cgroup v2 has a single hierarchy with ID 0 and no controllers
+        let current = membership
+            .lines()
+            .find_map(|line| line.strip_prefix("0::"))
+            .ok_or_else(|| MeasurementError::Unsupported("cgroup v2".to_string()))?;
+        // Hybrid setups mount the v2 hierarchy next to the v1 controllers
+        let parent = ["sys/fs/cgroup", "sys/fs/cgroup/unified"]
+            .iter()
+            .map(|mount| {
+                root.join(mount)
+                    .join(current.trim().trim_start_matches('/'))
+            })
+            .find(|parent| parent.join("cgroup.procs").exists())
+            .ok_or_else(|| MeasurementError::Unsupported("cgroup v2".to_string()))?;
+
+        let group = parent.join(name);
+        fs::create_dir(&group)?;
+        let path = group.join("workload");
+        if let Err(e) = fs::create_dir(&path) {
+            let _ = fs::remove_dir(&group);
+            return Err(e.into());
+        }
+        // Memory and I/O statistics need their controllers enabled all the
+        // way down to the leaf. The created cgroup holds no processes, so
+        // it can enable whatever the parent passes on, but the parent
+        // cannot while it holds processes itself; the CPU statistics are
+        // always available
+        for cgroup in [&parent, &group] {
+            for controller in ["+memory", "+io"] {
+                let _ = fs::write(cgroup.join("cgroup.subtree_control"), controller);
+            }
+        }
+        Ok(Self { group, path })
+    }
+
+    /// Directory of the leaf the processes go into
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Which of the memory and I/O controllers are not enabled for the
+    /// leaf, leaving its memory or I/O statistics unavailable
+    pub fn missing_controllers(&self) -> Vec<&'static str> {
+        let enabled = fs::read_to_string(self.path.join("cgroup.controllers")).unwrap_or_default();
+        ["memory", "io"]
+            .into_iter()
+            .filter(|controller| !enabled.split_whitespace().any(|c| c == *controller))
+            .collect()
+    }
+
+    /// Opens `cgroup.procs` for writing; writing `0` to it moves the
+    /// writing process into the cgroup
+    pub fn procs_file(&self) -> Result<File, MeasurementError> {
+        Ok(OpenOptions::new()
+            .write(true)
+            .open(self.path.join("cgroup.procs"))?)
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir(&self.path);
+        let _ = fs::remove_dir(&self.group);
+    }
+}
+
+/// Reads `usage_usec` from `cpu.stat` of the cgroup at `path`
+pub(crate) fn read_cpu_usage(path: &Path) -> Result<u64, MeasurementError> {
+    let stat = fs::read_to_string(path.join("cpu.stat"))?;
+    stat.lines()
+        .find_map(|line| line.strip_prefix("usage_usec "))
+        .and_then(|usage| usage.trim().parse().ok())
+        .ok_or_else(|| {
+            MeasurementError::InvalidMeasurement("no usage_usec in cpu.stat".to_string())
+        })
+}
+
+/// Reads `memory.peak` in bytes, if the memory controller is enabled
+pub(crate) fn read_memory_peak(path: &Path) -> Option<u64> {
+    fs::read_to_string(path.join("memory.peak"))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Sums `rbytes` and `wbytes` over all devices of `io.stat`, if the I/O
+/// controller is enabled
+pub(crate) fn read_io_bytes(path: &Path) -> Option<(u64, u64)> {
+    let stat = fs::read_to_string(path.join("io.stat")).ok()?;
+    let mut bytes = (0, 0);
+    for field in stat.split_whitespace() {
+        let Some((key, value)) = field.split_once('=') else {
+            continue;
+        };
+        match (key, value.parse::<u64>()) {
+            ("rbytes", Ok(value)) => bytes.0 += value,
+            ("wbytes", Ok(value)) => bytes.1 += value,
+            _ => {}
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::FakeRoot;
+
+    #[test]
+    fn test_create_and_read_cgroup() {
+        let root = FakeRoot::new("cgroup-create");
+        root.file("proc/self/cgroup", "0::/user.slice/ci.service\n")
+            .file("sys/fs/cgroup/user.slice/ci.service/cgroup.procs", "");
+
+        let cgroup = Cgroup::create(root.path(), "energy-1").unwrap();
+        let path = cgroup.path().to_path_buf();
+        let group = root
+            .path()
+            .join("sys/fs/cgroup/user.slice/ci.service/energy-1");
+        assert_eq!(path, group.join("workload"));
+        assert_eq!(cgroup.missing_controllers(), ["memory", "io"]);
+
+        root.file(
+            "sys/fs/cgroup/user.slice/ci.service/energy-1/workload/cgroup.controllers",
+            "cpu io memory pids\n",
+        )
+        .file(
+            "sys/fs/cgroup/user.slice/ci.service/energy-1/workload/cpu.stat",
+            "usage_usec 1500\nuser_usec 1000\nsystem_usec 500\n",
+        )
+        .file(
+            "sys/fs/cgroup/user.slice/ci.service/energy-1/workload/io.stat",
+            "8:0 rbytes=4096 wbytes=512 rios=1 wios=1 dbytes=0 dios=0\n\
+             259:0 rbytes=1024 wbytes=0 rios=1 wios=0 dbytes=0 dios=0\n",
+        );
+        assert_eq!(read_cpu_usage(&path).unwrap(), 1500);
+        assert_eq!(read_io_bytes(&path), Some((5120, 512)));
+        assert_eq!(read_memory_peak(&path), None);
+        assert!(cgroup.missing_controllers().is_empty());
+    }
+
+    #[test]
+    fn test_create_hybrid_cgroup() {
+        let root = FakeRoot::new("cgroup-hybrid");
+        root.file("proc/self/cgroup", "1:cpu:/\n0::/ci.service\n")
+            .file("sys/fs/cgroup/cpu/cgroup.procs", "")
+            .file("sys/fs/cgroup/unified/ci.service/cgroup.procs", "");
+
+        let cgroup = Cgroup::create(root.path(), "energy-1").unwrap();
+        assert_eq!(
+            cgroup.path(),
+            root.path()
+                .join("sys/fs/cgroup/unified/ci.service/energy-1/workload")
+        );
+    }
+
+    #[test]
+    fn test_cgroup_v1_unsupported() {
+        let root = FakeRoot::new("cgroup-v1");
+        root.file("proc/self/cgroup", "12:cpu,cpuacct:/user.slice\n");
+        assert!(matches!(
+            Cgroup::create(root.path(), "energy-1"),
+            Err(MeasurementError::Unsupported(_))
+        ));
+    }
+}
@@ -0,0 +1,630 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+use uom::si::energy::joule;
+use uom::si::f64::Energy;
+
+use crate::attribution::ProcessTreeTracker;
+use crate::cgroup::read_cpu_usage;
+use crate::procfs::{clock_ticks_per_second, read_system_cpu_times, ProcessScan};
+use crate::{
+    AttributionMode, EnergyMeasurement, MeasurementConfig, MeasurementError, MeterCapabilities,
+    PowerMeter, PowerSource, WorkloadProcesses,
+};
+
+/// Conservative TDP for the estimate, typical of a laptop CPU
+const DEFAULT_TDP_WATTS: f64 = 28.0;
+
+/// Idle power as a fraction of max power for the estimate, from Cloud
+/// Carbon Footprint's averages of 0.74 W idle and 3.5 W max per vCPU
+const DEFAULT_IDLE_FRACTION: f64 = 0.2;
+
+/// Per-package power figures of common CPUs, see the header for the format
+const CPU_POWER_TABLE: &str = include_str!("cpu_power.csv");
+
+/// Where the figures of a [`CpuProfile`] come from
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum CpuProfileSource {
+    /// The TDP was set in [`MeasurementConfig`]
+    Configured,
+    /// The named entry of the embedded CPU table matched the model name
+    Table(String),
+    /// The CPU is unknown, so the defaults were used
+    Default,
+}
+
+/// Power figures for the local CPU used by the TDP estimate
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CpuProfile {
+    /// `model name` from `/proc/cpuinfo`, if present
+    pub model: Option<String>,
+    /// Where the power figures come from
+    pub source: CpuProfileSource,
+    /// Number of CPU packages the figures were scaled by
+    pub sockets: u32,
+    /// TDP of all packages in watts
+    pub tdp_watts: f64,
+    /// Power at idle of all packages in watts
+    pub idle_watts: f64,
+    /// Power at full utilization of all packages in watts
+    pub max_watts: f64,
+}
+
+impl CpuProfile {
+    /// Looks up the CPU described by `proc/cpuinfo` below `root` in the
+    /// embedded table, falling back to the defaults
+    pub fn detect(root: impl AsRef<Path>) -> Self {
+        let table = parse_power_table(CPU_POWER_TABLE);
+        Self::resolve(root.as_ref(), &table, None, None, None)
+    }
+
+    /// Like [`CpuProfile::detect`], with any power figures set in `config`
+    /// taking precedence over the table
+    pub(crate) fn from_config(config: &MeasurementConfig) -> Self {
+        Self::resolve(
+            &config.fs_root,
+            &parse_power_table(CPU_POWER_TABLE),
+            config.tdp_watts,
+            config.idle_watts,
+            config.max_watts,
+        )
+    }
+
+    /// Looks up the CPU in `table`, with any of the given power figures
+    /// taking precedence
+    fn resolve(
+        root: &Path,
+        table: &[CpuPowerEntry],
+        tdp_watts: Option<f64>,
+        idle_watts: Option<f64>,
+        max_watts: Option<f64>,
+    ) -> Self {
+        let cpuinfo = fs::read_to_string(root.join("proc/cpuinfo")).unwrap_or_default();
+        let (model, sockets) = parse_cpuinfo(&cpuinfo);
+        // A configured TDP replaces the table entry as a whole
+        let entry = match tdp_watts {
+            Some(_) => None,
+            None => model.as_deref().and_then(|model| lookup(table, model)),
+        };
+
+        let scale = |watts: Option<f64>| watts.map(|watts| watts * sockets as f64);
+        let (source, tdp) = match (tdp_watts, entry) {
+            (Some(tdp), _) => (CpuProfileSource::Configured, tdp),
+            (None, Some(entry)) => (
+                CpuProfileSource::Table(entry.model.clone()),
+                entry.tdp_watts * sockets as f64,
+            ),
+            (None, None) => (CpuProfileSource::Default, DEFAULT_TDP_WATTS),
+        };
+        let max = max_watts
+            .or_else(|| scale(entry.and_then(|entry| entry.max_watts)))
+            .unwrap_or(tdp);
+        let idle = idle_watts
+            .or_else(|| scale(entry.and_then(|entry| entry.idle_watts)))
+            .unwrap_or(max * DEFAULT_IDLE_FRACTION);
+
+        Self {
+            model,
+            source,
+            sockets,
+            tdp_watts: tdp,
+            idle_watts: idle,
+            max_watts: max,
+        }
+    }
+}
+
+impl Display for CpuProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.model.as_deref().unwrap_or("unknown CPU"))?;
+        if self.sockets > 1 {
+            write!(f, " x{}", self.sockets)?;
+        }
+        match &self.source {
+            CpuProfileSource::Configured => write!(f, " (configured")?,
+            CpuProfileSource::Table(entry) => write!(f, " (matched \"{}\"", entry)?,
+            CpuProfileSource::Default => write!(f, " (no match, default")?,
+        }
+        write!(
+            f,
+            ", {:.0} W TDP, {:.1}-{:.1} W)",
+            self.tdp_watts, self.idle_watts, self.max_watts
+        )
+    }
+}
+
+/// A row of the embedded CPU power table
+#[derive(Debug, Clone, PartialEq)]
+struct CpuPowerEntry {
+    /// Normalized model name
+    model: String,
+    tdp_watts: f64,
+    idle_watts: Option<f64>,
+    max_watts: Option<f64>,
+}
+
+fn parse_power_table(table: &str) -> Vec<CpuPowerEntry> {
+    table
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        // Skip the column names
+        .skip(1)
+        .filter_map(|line| {
+            let mut columns = line.split(',').map(str::trim);
+            let model = columns.next()?.to_string();
+            let tdp_watts = columns.next()?.parse().ok()?;
+            let mut optional = || columns.next().and_then(|watts| watts.parse().ok());
+            Some(CpuPowerEntry {
+                model,
+                tdp_watts,
+                idle_watts: optional(),
+                max_watts: optional(),
+            })
+        })
+        .collect()
+}
+
+/// Splits a model name into lowercase words, dropping trademark symbols,
+/// filler words and the clock speed
+fn normalize_model(model: &str) -> Vec<String> {
+    let model = model.to_lowercase().replace("(r)", "").replace("(tm)", "");
+    let model = model.split(" @ ").next().unwrap_or_default();
+    model
+        .split_whitespace()
+        .filter(|word| !matches!(*word, "cpu" | "processor"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Finds the entry whose words appear in sequence in `model`, preferring
+/// the most specific one
+fn lookup<'a>(table: &'a [CpuPowerEntry], model: &str) -> Option<&'a CpuPowerEntry> {
+    let words = normalize_model(model);
+    table
+        .iter()
+        .filter(|entry| {
+            let key: Vec<&str> = entry.model.split_whitespace().collect();
+            !key.is_empty() && words.windows(key.len()).any(|window| window == key)
+        })
+        .max_by_key(|entry| entry.model.split_whitespace().count())
+}
+
+/// Extracts the model name and the number of packages from `/proc/cpuinfo`
+fn parse_cpuinfo(cpuinfo: &str) -> (Option<String>, u32) {
+    let mut model = None;
+    let mut packages = Vec::new();
+    for line in cpuinfo.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        match key.trim() {
+            "model name" if model.is_none() => model = Some(value.trim().to_string()),
+            "physical id" => {
+                let value = value.trim().to_string();
+                if !packages.contains(&value) {
+                    packages.push(value);
+                }
+            }
+            _ => {}
+        }
+    }
+    (model, (packages.len() as u32).max(1))
+}
+
+/// Which CPU time drives the utilization of a [`CpuPowerModel`]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UtilizationScope {
+    /// Busy time of the whole machine from `/proc/stat`, comparable to
+    /// what the hardware meters report
+    #[default]
+    System,
+    /// CPU time of the workload: the cgroup it is attributed by, or the
+    /// live process tree of the [`WorkloadProcesses`]
+    Workload,
+}
+
+impl Display for UtilizationScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UtilizationScope::System => write!(f, "system"),
+            UtilizationScope::Workload => write!(f, "workload"),
+        }
+    }
+}
+
+impl FromStr for UtilizationScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "system" => Ok(UtilizationScope::System),
+            "workload" => Ok(UtilizationScope::Workload),
+            _ => Err(format!("Unknown utilization scope: {}", s)),
+        }
+    }
+}
+
+/// Where the CPU time of the workload scope is read from
+enum WorkloadSource {
+    /// Running and exited members of a process tree
+    ProcessTree(ProcessTreeTracker),
+    /// `usage_usec` of a cgroup v2
+    Cgroup(PathBuf),
+}
+
+/// Estimates CPU power by interpolating between idle and maximum power
+/// according to CPU utilization
+///
+/// This is the linear model used by Cloud Carbon Footprint:
+/// `idle + utilization * (max - idle)`, integrated over each sample interval.
+pub struct CpuPowerModel {
+    profile: Option<CpuProfile>,
+    idle_watts: f64,
+    max_watts: f64,
+    scope: UtilizationScope,
+    root: PathBuf,
+    /// Unset for the system scope
+    workload: Option<WorkloadSource>,
+    ticks_per_second: f64,
+    cpus: usize,
+    /// (busy, total) ticks of the last reading; total is unused for the
+    /// workload scope, where wall time stands in for it
+    last_ticks: (u64, u64),
+    last_time: Instant,
+    /// Accumulated energy in joules
+    energy: f64,
+}
+
+impl CpuPowerModel {
+    /// Creates a model drawing `idle_watts` at rest and `max_watts` at full
+    /// utilization
+    pub fn new(
+        idle_watts: f64,
+        max_watts: f64,
+        scope: UtilizationScope,
+    ) -> Result<Self, MeasurementError> {
+        Self::with_root(idle_watts, max_watts, scope, "/")
+    }
+
+    /// Creates a model reading `proc` below `root` instead of `/`; the
+    /// workload scope follows this process and its descendants
+    pub fn with_root(
+        idle_watts: f64,
+        max_watts: f64,
+        scope: UtilizationScope,
+        root: impl AsRef<Path>,
+    ) -> Result<Self, MeasurementError> {
+        let root = root.as_ref().to_path_buf();
+        let cpus = read_system_cpu_times(&root)?.cpus;
+        let workload = match scope {
+            UtilizationScope::System => None,
+            UtilizationScope::Workload => Some(WorkloadSource::ProcessTree(
+                ProcessTreeTracker::new(&root, std::process::id(), WorkloadProcesses::Current)?,
+            )),
+        };
+        let mut model = Self {
+            profile: None,
+            idle_watts,
+            max_watts: max_watts.max(idle_watts),
+            scope,
+            root,
+            workload,
+            ticks_per_second: clock_ticks_per_second(),
+            cpus,
+            last_ticks: (0, 0),
+            last_time: Instant::now(),
+            energy: 0.0,
+        };
+        model.last_ticks = model.read_ticks()?;
+        Ok(model)
+    }
+
+    /// Reports `profile` as the origin of the power figures
+    pub fn with_profile(mut self, profile: CpuProfile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Follows the workload of `config` for the workload scope: the cgroup
+    /// it is attributed by, or the process tree of its
+    /// [`WorkloadProcesses`]
+    pub(crate) fn following(
+        mut self,
+        config: &MeasurementConfig,
+    ) -> Result<Self, MeasurementError> {
+        if self.workload.is_none() {
+            return Ok(self);
+        }
+        self.workload = Some(match &config.attribution {
+            AttributionMode::Cgroup(path) => WorkloadSource::Cgroup(path.clone()),
+            _ => WorkloadSource::ProcessTree(ProcessTreeTracker::new(
+                &self.root,
+                std::process::id(),
+                config.workload_processes,
+            )?),
+        });
+        self.last_ticks = self.read_ticks()?;
+        Ok(self)
+    }
+
+    fn read_ticks(&mut self) -> Result<(u64, u64), MeasurementError> {
+        match &mut self.workload {
+            None => {
+                let times = read_system_cpu_times(&self.root)?;
+                Ok((times.busy, times.total))
+            }
+            // Running descendants are counted as they go, not only once
+            // they are reaped
+            Some(WorkloadSource::ProcessTree(tracker)) => {
+                tracker.sample(&ProcessScan::default())?;
+                Ok((tracker.ticks(), 0))
+            }
+            Some(WorkloadSource::Cgroup(path)) => {
+                let usage = read_cpu_usage(path)?;
+                Ok(((usage as f64 / 1e6 * self.ticks_per_second) as u64, 0))
+            }
+        }
+    }
+
+    /// Utilization between two readings taken `seconds` apart
+    fn utilization(&self, last: (u64, u64), now: (u64, u64), seconds: f64) -> f64 {
+        let busy = now.0.saturating_sub(last.0) as f64;
+        let utilization = match self.scope {
+            UtilizationScope::System => {
+                let total = now.1.saturating_sub(last.1) as f64;
+                if total > 0.0 {
+                    busy / total
+                } else {
+                    0.0
+                }
+            }
+            UtilizationScope::Workload if seconds > 0.0 => {
+                busy / self.ticks_per_second / (seconds * self.cpus as f64)
+            }
+            UtilizationScope::Workload => 0.0,
+        };
+        utilization.clamp(0.0, 1.0)
+    }
+
+    fn power(&self, utilization: f64) -> f64 {
+        self.idle_watts + utilization * (self.max_watts - self.idle_watts)
+    }
+}
+
+impl PowerMeter for CpuPowerModel {
+    fn source(&self) -> PowerSource {
+        PowerSource::TdpEstimate
+    }
+
+    fn capabilities(&self) -> MeterCapabilities {
+        MeterCapabilities {
+            instantaneous_power: false,
+            cumulative_energy: true,
+            power_resolution: None,
+            energy_resolution: None,
+        }
+    }
+
+    fn read_energy(&mut self) -> Result<Energy, MeasurementError> {
+        let ticks = self.read_ticks()?;
+        let now = Instant::now();
+        let seconds = now.duration_since(self.last_time).as_secs_f64();
+
+        let utilization = self.utilization(self.last_ticks, ticks, seconds);
+        self.energy += self.power(utilization) * seconds;
+        self.last_ticks = ticks;
+        self.last_time = now;
+
+        Ok(Energy::new::<joule>(self.energy))
+    }
+
+    fn annotate(&self, measurement: &mut EnergyMeasurement) {
+        measurement.cpu_profile = self.profile.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::FakeRoot;
+
+    #[test]
+    fn test_system_utilization() {
+        let root = FakeRoot::new("cpu-model-system");
+        root.file(
+            "proc/stat",
+            "cpu  100 0 100 800 0 0 0 0 0 0\ncpu0 0\ncpu1 0\n",
+        );
+        let model =
+            CpuPowerModel::with_root(5.0, 45.0, UtilizationScope::System, root.path()).unwrap();
+        assert_eq!(model.cpus, 2);
+        assert_eq!(model.last_ticks, (200, 1000));
+
+        // 300 of 1000 new ticks were busy
+        let utilization = model.utilization((200, 1000), (500, 2000), 1.0);
+        assert!((utilization - 0.3).abs() < 1e-9);
+        assert!((model.power(utilization) - 17.0).abs() < 1e-9);
+        // Idle draw remains when nothing runs
+        assert_eq!(
+            model.power(model.utilization((200, 1000), (200, 2000), 1.0)),
+            5.0
+        );
+    }
+
+    #[test]
+    fn test_workload_utilization() {
+        let root = FakeRoot::new("cpu-model-workload");
+        let (pid, child) = (std::process::id(), std::process::id() + 1);
+        let stat = |pid: u32, ppid: u32, cpu: u64| {
+            format!("{pid} (worker) S {ppid} 1 1 0 -1 0 0 0 0 0 {cpu} 0 0 0 20 0 1 0 1 0 0")
+        };
+        root.file(
+            "proc/stat",
+            "cpu  0 0 0 0\ncpu0 0\ncpu1 0\ncpu2 0\ncpu3 0\n",
+        )
+        .file(&format!("proc/{pid}/stat"), &stat(pid, 1, 20))
+        .file(&format!("proc/{child}/stat"), &stat(child, pid, 0));
+        let mut model =
+            CpuPowerModel::with_root(10.0, 50.0, UtilizationScope::Workload, root.path()).unwrap();
+        model.ticks_per_second = 100.0;
+        assert_eq!(model.last_ticks, (20, 0));
+
+        // A child keeping two of four cores busy is counted at every
+        // sample while it runs, not only once it is reaped
+        for second in 1..=3 {
+            root.file(
+                &format!("proc/{child}/stat"),
+                &stat(child, pid, 200 * second),
+            );
+            let ticks = model.read_ticks().unwrap();
+            assert_eq!(ticks, (20 + 200 * second, 0));
+            let utilization = model.utilization(model.last_ticks, ticks, 1.0);
+            assert!((utilization - 0.5).abs() < 1e-9);
+            assert!((model.power(utilization) - 30.0).abs() < 1e-9);
+            model.last_ticks = ticks;
+        }
+
+        // Attributed by cgroup, the cgroup's usage counts instead
+        root.file("sys/fs/cgroup/job/cpu.stat", "usage_usec 2000000\n");
+        let config = MeasurementConfig {
+            attribution: AttributionMode::Cgroup(root.path().join("sys/fs/cgroup/job")),
+            ..Default::default()
+        };
+        let model = model.following(&config).unwrap();
+        assert_eq!(model.last_ticks, (200, 0));
+    }
+
+    #[test]
+    fn test_power_table_parses() {
+        let table = parse_power_table(CPU_POWER_TABLE);
+        assert!(table.len() > 50);
+        assert!(table.iter().all(|entry| entry.tdp_watts > 0.0));
+        // Every key must be reachable through normalization
+        for entry in &table {
+            assert_eq!(normalize_model(&entry.model).join(" "), entry.model);
+        }
+    }
+
+    #[test]
+    fn test_lookup_model_names() {
+        let table = parse_power_table(CPU_POWER_TABLE);
+        let matched = |model: &str| lookup(&table, model).map(|entry| entry.model.as_str());
+
+        assert_eq!(
+            matched("Intel(R) Core(TM) i7-8650U CPU @ 1.90GHz"),
+            Some("intel core i7-8650u")
+        );
+        assert_eq!(
+            matched("11th Gen Intel(R) Core(TM) i7-1165G7 @ 2.80GHz"),
+            Some("intel core i7-1165g7")
+        );
+        assert_eq!(
+            matched("Intel(R) Xeon(R) CPU E5-2680 v4 @ 2.40GHz"),
+            Some("intel xeon e5-2680 v4")
+        );
+        assert_eq!(
+            matched("AMD EPYC 7763 64-Core Processor"),
+            Some("amd epyc 7763")
+        );
+        // Whole words only, the K model is a different entry
+        assert_eq!(
+            matched("Intel(R) Core(TM) i7-8700K CPU @ 3.70GHz"),
+            Some("intel core i7-8700k")
+        );
+        assert_eq!(matched("Intel(R) Core(TM) i7-87000"), None);
+    }
+
+    #[test]
+    fn test_profile_detection() {
+        let root = FakeRoot::new("cpuinfo-server");
+        let mut cpuinfo = String::new();
+        for (processor, package) in [(0, 0), (1, 0), (2, 1), (3, 1)] {
+            cpuinfo.push_str(&format!(
+                "processor\t: {processor}\n\
+                 model name\t: Intel(R) Xeon(R) Gold 6148 CPU @ 2.40GHz\n\
+                 physical id\t: {package}\n\n"
+            ));
+        }
+        root.file("proc/cpuinfo", &cpuinfo);
+
+        let profile = CpuProfile::detect(root.path());
+        assert_eq!(
+            profile.source,
+            CpuProfileSource::Table("intel xeon gold 6148".to_string())
+        );
+        assert_eq!(profile.sockets, 2);
+        assert_eq!(profile.tdp_watts, 300.0);
+        assert_eq!(profile.max_watts, 300.0);
+        assert_eq!(profile.idle_watts, 60.0);
+
+        // Configured figures win over the table
+        let config = MeasurementConfig {
+            fs_root: root.path().to_path_buf(),
+            tdp_watts: Some(100.0),
+            idle_watts: Some(12.0),
+            ..Default::default()
+        };
+        let profile = CpuProfile::from_config(&config);
+        assert_eq!(profile.source, CpuProfileSource::Configured);
+        assert_eq!((profile.idle_watts, profile.max_watts), (12.0, 100.0));
+
+        let root = FakeRoot::new("cpuinfo-arm");
+        root.file("proc/cpuinfo", "processor\t: 0\nCPU part\t: 0xd0c\n");
+        let profile = CpuProfile::detect(root.path());
+        assert_eq!(profile.model, None);
+        assert_eq!(profile.source, CpuProfileSource::Default);
+        assert_eq!(profile.tdp_watts, DEFAULT_TDP_WATTS);
+    }
+
+    #[test]
+    fn test_table_idle_and_max() {
+        let table = parse_power_table("model,tdp_watts,idle_watts,max_watts\nacme x1,100,8,120\n");
+        assert_eq!(
+            table,
+            [CpuPowerEntry {
+                model: "acme x1".to_string(),
+                tdp_watts: 100.0,
+                idle_watts: Some(8.0),
+                max_watts: Some(120.0),
+            }]
+        );
+
+        // Table figures replace the estimate from the TDP, per package
+        let root = FakeRoot::new("cpuinfo-acme");
+        root.file(
+            "proc/cpuinfo",
+            "processor\t: 0\nmodel name\t: ACME X1\nphysical id\t: 0\n\n\
+             processor\t: 1\nmodel name\t: ACME X1\nphysical id\t: 1\n",
+        );
+        let profile = CpuProfile::resolve(root.path(), &table, None, None, None);
+        assert_eq!(profile.tdp_watts, 200.0);
+        assert_eq!((profile.idle_watts, profile.max_watts), (16.0, 240.0));
+        let profile = CpuProfile::resolve(root.path(), &table, None, Some(10.0), None);
+        assert_eq!((profile.idle_watts, profile.max_watts), (10.0, 240.0));
+
+        // Without an idle figure it is a fifth of the table's max
+        let root = FakeRoot::new("cpuinfo-ryzen");
+        root.file(
+            "proc/cpuinfo",
+            "processor\t: 0\nmodel name\t: AMD Ryzen 9 5950X 16-Core Processor\n",
+        );
+        let profile = CpuProfile::detect(root.path());
+        assert_eq!(profile.tdp_watts, 105.0);
+        assert_eq!(profile.max_watts, 142.0);
+        assert!((profile.idle_watts - 28.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_cpu_model_unavailable() {
+        let root = FakeRoot::new("cpu-model-none");
+        assert!(
+            CpuPowerModel::with_root(5.0, 45.0, UtilizationScope::System, root.path()).is_err()
+        );
+    }
+}
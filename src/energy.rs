@@ -1,9 +1,31 @@
 use argh::FromArgs;
 use carbonara::{
-    BenchmarkExecutor, EnergyMeasurement, MeasurementConfig, MeasurementError, PowerSource,
+    joules_to_kwh, kwh_to_co2e, AttributionMode, BenchmarkExecutor, Cgroup, ComparedWorkload,
+    Comparison, EnergyMeasurement, MeasurementConfig, MeasurementError, PhaseMarkers, PowerSource,
+    RepeatedMeasurement, StorageCoefficients, Summary, UtilizationScope, WorkloadProcesses,
+    DEFAULT_MEMORY_WATTS_PER_GB, SIGNIFICANCE_LEVEL,
 };
 use okstd::prelude::*;
-use std::{convert::Infallible, fmt::Display, process::Command, str::FromStr, time::Duration};
+use std::{
+    convert::Infallible,
+    env,
+    ffi::{CString, OsStr},
+    fmt::Display,
+    fs::{self, File},
+    future::Future,
+    io,
+    os::{fd::AsRawFd, unix::ffi::OsStrExt},
+    path::{Path, PathBuf},
+    pin::pin,
+    process::ExitStatus,
+    str::FromStr,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    net::unix::pipe,
+    process::Command,
+};
 use uom::si::{
     energy::{joule, kilowatt_hour},
     power::watt,
@@ -14,6 +36,7 @@ enum Format {
     Human,
     Json,
     Csv,
+    Samples,
 }
 
 impl Display for Format {
@@ -22,6 +45,7 @@ impl Display for Format {
             Format::Human => write!(f, "human"),
             Format::Json => write!(f, "json"),
             Format::Csv => write!(f, "csv"),
+            Format::Samples => write!(f, "samples"),
         }
     }
 }
@@ -33,6 +57,7 @@ impl FromStr for Format {
             "human" => Ok(Format::Human),
             "json" => Ok(Format::Json),
             "csv" => Ok(Format::Csv),
+            "samples" => Ok(Format::Samples),
             _ => unreachable!(),
         }
     }
@@ -41,7 +66,7 @@ impl FromStr for Format {
 #[derive(FromArgs)]
 /// A CLI tool like `time` but for energy consumption.
 struct EnergyTool {
-    /// measurement method to use (rapl, acpi, tdp)
+    /// measurement method to use (rapl, perf, hwmon, acpi, acpi-energy, tdp)
     #[argh(option, short = 'm', default = "PowerSource::Acpi")]
     method: PowerSource,
 
@@ -49,35 +74,759 @@ struct EnergyTool {
     #[argh(option, short = 'i', default = "100")]
     interval: u64,
 
-    /// output format (human, json, csv)
+    /// output format (human, json, csv, or samples for the power readings
+    /// as csv)
     #[argh(option, short = 'f', default = "Format::Human")]
     format: Format,
 
-    /// duration to measure for in milliseconds
-    #[argh(option, short = 'd', default = "1000")]
-    duration: u64,
+    /// keep measuring for at least this many milliseconds when the
+    /// command finishes sooner
+    #[argh(option)]
+    min_duration: Option<u64>,
+
+    /// stop measuring after this many milliseconds even if the command is
+    /// still running
+    #[argh(option, short = 'd')]
+    max_duration: Option<u64>,
 
     /// co2e_per_kwh - The CO2e per kWh (e.g., 436 gCO2e/kWh for global average)
     #[argh(option, short = 'c', default = "436.0")]
     co2e_per_kwh: f64,
 
-    /// the command to run and measure
+    /// CPU TDP in watts for the tdp method
+    #[argh(option)]
+    tdp: Option<f64>,
+
+    /// CPU power at idle in watts for the tdp method
+    #[argh(option)]
+    idle_watts: Option<f64>,
+
+    /// CPU power at full load in watts for the tdp method
+    #[argh(option)]
+    max_watts: Option<f64>,
+
+    /// CPU time driving the tdp method (system, workload)
+    #[argh(option, default = "UtilizationScope::System")]
+    utilization: UtilizationScope,
+
+    /// attribute the system energy to the command (off, process-tree)
+    #[argh(option, short = 'a', default = "AttributionMode::Off")]
+    attribution: AttributionMode,
+
+    /// run the command in its own cgroup v2 and attribute energy by its
+    /// CPU usage, falling back to the process tree
+    #[argh(switch)]
+    cgroup: bool,
+
+    /// estimate memory energy from the command's resident memory
+    #[argh(switch)]
+    memory: bool,
+
+    /// DRAM power per GB of resident memory, implies --memory
+    #[argh(option)]
+    memory_watts_per_gb: Option<f64>,
+
+    /// estimate the energy of data sent and received during the run
+    #[argh(switch)]
+    network: bool,
+
+    /// estimate the energy of storage I/O during the run
+    #[argh(switch)]
+    storage: bool,
+
+    /// joules per GB of SSD I/O, implies --storage
+    #[argh(option)]
+    ssd_joules_per_gb: Option<f64>,
+
+    /// joules per GB of hard drive I/O, implies --storage
+    #[argh(option)]
+    hdd_joules_per_gb: Option<f64>,
+
+    /// measure idle power for this many milliseconds before the command
+    /// and report the energy net of it
+    #[argh(option)]
+    baseline: Option<u64>,
+
+    /// measure idle power after the command as well
+    #[argh(switch)]
+    baseline_after: bool,
+
+    /// number of measured runs of the command, 1 by default, or of rounds
+    /// with --compare, 10 by default
+    #[argh(option, short = 'r')]
+    runs: Option<usize>,
+
+    /// number of runs before the measured ones that are discarded
+    #[argh(option, short = 'w', default = "0")]
+    warmup: usize,
+
+    /// compare several shell commands, each given as one argument, running
+    /// them in turn for --runs rounds, at least 2 for a significance test
+    #[argh(switch)]
+    compare: bool,
+
+    /// the command to run and measure; it can split the measurement into
+    /// phases by writing their names, one per line, to the FIFO named in
+    /// $ENERGY_MARKERS
     #[argh(positional)]
     command: Vec<String>,
 }
 
+/// Rounds of `--compare` unless `--runs` is given
+const DEFAULT_COMPARE_ROUNDS: usize = 10;
+
+/// Environment variable with the path of the command's [`MarkerFifo`]
+const MARKERS_ENV: &str = "ENERGY_MARKERS";
+
+/// Creates a cgroup for the command and attributes by it, falling back to
+/// the process tree when cgroups are not delegated to us
+fn open_cgroup(config: &mut MeasurementConfig) -> Option<(Cgroup, File)> {
+    let opened = Cgroup::create("/", &format!("energy-{}", std::process::id()))
+        .and_then(|cgroup| Ok((cgroup.procs_file()?, cgroup)));
+    match opened {
+        Ok((procs, cgroup)) => {
+            config.attribution = AttributionMode::Cgroup(cgroup.path().to_path_buf());
+            Some((cgroup, procs))
+        }
+        Err(e) => {
+            eprintln!(
+                "Warning: cgroup accounting unavailable ({:?}), attributing by process tree",
+                e
+            );
+            config.attribution = AttributionMode::ProcessTree;
+            None
+        }
+    }
+}
+
+/// How long reading markers may go on after the command exits, for a
+/// process it left running that holds the FIFO open
+const MARKER_DRAIN: Duration = Duration::from_millis(100);
+
+/// A FIFO the command can write phase names to, in a private directory
+/// removed on drop
+struct MarkerFifo {
+    path: PathBuf,
+}
+
+impl MarkerFifo {
+    fn create() -> io::Result<Self> {
+        let template = env::temp_dir().join("energy-XXXXXX");
+        let mut template = CString::new(template.as_os_str().as_bytes())?.into_bytes_with_nul();
+        // SAFETY: `template` is a NUL-terminated buffer that mkdtemp fills
+        // in place and that outlives the call
+        if unsafe { libc::mkdtemp(template.as_mut_ptr().cast()) }.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        template.pop();
+        let directory = PathBuf::from(OsStr::from_bytes(&template));
+        let path = directory.join("markers");
+
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        // SAFETY: `c_path` is a NUL-terminated string that outlives the call
+        if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+            let error = io::Error::last_os_error();
+            let _ = fs::remove_dir(&directory);
+            return Err(error);
+        }
+        Ok(Self { path })
+    }
+
+    /// Creates the FIFO, or warns and goes without phase markers
+    fn create_or_warn() -> Option<Self> {
+        Self::create()
+            .map_err(|e| eprintln!("Warning: phase markers unavailable ({})", e))
+            .ok()
+    }
+}
+
+impl Drop for MarkerFifo {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+        if let Some(directory) = self.path.parent() {
+            let _ = fs::remove_dir(directory);
+        }
+    }
+}
+
+/// Runs `command` to completion, in the cgroup whose `cgroup.procs` is
+/// open as `procs`, with the path of `markers`, if any, in its environment
+async fn run_command(
+    command: &[String],
+    procs: Option<&File>,
+    markers: Option<&Path>,
+) -> io::Result<ExitStatus> {
+    let mut cmd = Command::new(&command[0]);
+    cmd.args(&command[1..]);
+    if let Some(markers) = markers {
+        cmd.env(MARKERS_ENV, markers);
+    }
+    if let Some(procs) = procs {
+        let fd = procs.as_raw_fd();
+        // SAFETY: the hook only calls write(2), which is async-signal-safe,
+        // on a descriptor that stays open until the command is spawned
+        unsafe {
+            cmd.pre_exec(move || {
+                // Writing 0 moves the forked child before it executes
+                if libc::write(fd, b"0".as_ptr().cast(), 1) == 1 {
+                    Ok(())
+                } else {
+                    Err(io::Error::last_os_error())
+                }
+            });
+        }
+    }
+    cmd.status().await
+}
+
+/// Runs `command`, starting a phase for each line it writes to `fifo`
+async fn run_marked(
+    command: &[String],
+    procs: Option<&File>,
+    fifo: &MarkerFifo,
+    markers: PhaseMarkers,
+) -> io::Result<ExitStatus> {
+    // The reading end opens without a writer, which lets the writing end
+    // open without blocking. The sender is held until the command exits,
+    // so that the command closing the FIFO between markers does not end
+    // the reading
+    let pipes = pipe::OpenOptions::new()
+        .open_receiver(&fifo.path)
+        .and_then(|receiver| Ok((receiver, pipe::OpenOptions::new().open_sender(&fifo.path)?)));
+    let (receiver, sender) = match pipes {
+        Ok(pipes) => pipes,
+        Err(e) => {
+            warn_markers(e);
+            return run_command(command, procs, None).await;
+        }
+    };
+
+    let run = async move {
+        let status = run_command(command, procs, Some(&fifo.path)).await;
+        drop(sender);
+        status
+    };
+    run_reading(run, read_markers(receiver, &markers)).await
+}
+
+/// Starts a phase for each line read from `reader`
+async fn read_markers(reader: impl AsyncRead + Unpin, markers: &PhaseMarkers) -> io::Result<()> {
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let name = line.trim();
+        if !name.is_empty() {
+            markers.mark(name);
+        }
+    }
+    Ok(())
+}
+
+/// Awaits `run` while `read` reads markers, going on without them if
+/// reading fails
+async fn run_reading<T>(
+    run: impl Future<Output = T>,
+    read: impl Future<Output = io::Result<()>>,
+) -> T {
+    let (mut run, mut read) = (pin!(run), pin!(read));
+    let output = tokio::select! {
+        output = &mut run => output,
+        read = &mut read => {
+            if let Err(e) = read {
+                warn_markers(e);
+            }
+            return run.await;
+        }
+    };
+    // Reading ends as soon as the markers written before the command
+    // exited are read, unless a process it left behind still holds the
+    // FIFO open; its markers would fall after the measurement anyway
+    if let Ok(Err(e)) = tokio::time::timeout(MARKER_DRAIN, read).await {
+        warn_markers(e);
+    }
+    output
+}
+
+fn warn_markers(error: io::Error) {
+    eprintln!(
+        "Warning: reading phase markers failed ({}), measuring without them",
+        error
+    );
+}
+
+/// Measures a single run of `command`
+async fn measure_run(
+    exec: &BenchmarkExecutor,
+    command: &[String],
+    procs: Option<&File>,
+    fifo: Option<&MarkerFifo>,
+) -> Result<EnergyMeasurement, MeasurementError> {
+    let (status, measurement) = match fifo {
+        Some(fifo) => {
+            exec.measure_async_with_markers(|markers| run_marked(command, procs, fifo, markers))
+                .await?
+        }
+        None => {
+            exec.measure_async(run_command(command, procs, None))
+                .await?
+        }
+    };
+    status?;
+    Ok(measurement)
+}
+
 async fn measure_command(
     command: Vec<String>,
-    config: MeasurementConfig,
-) -> Result<EnergyMeasurement, MeasurementError> {
+    mut config: MeasurementConfig,
+    cgroup: bool,
+    runs: usize,
+    warmup: usize,
+) -> Result<RepeatedMeasurement, MeasurementError> {
+    // The cgroup is removed on drop, once the command has exited
+    let (_cgroup, procs) = match cgroup.then(|| open_cgroup(&mut config)).flatten() {
+        Some((cgroup, procs)) => (Some(cgroup), Some(procs)),
+        None => (None, None),
+    };
+
+    let fifo = MarkerFifo::create_or_warn();
+    let exec = BenchmarkExecutor::new(config);
+    let mut measurements = Vec::with_capacity(runs);
+    for run in 0..warmup + runs {
+        let measurement = measure_run(&exec, &command, procs.as_ref(), fifo.as_ref()).await?;
+        if run >= warmup {
+            measurements.push(measurement);
+        }
+    }
+    RepeatedMeasurement::new(measurements, warmup)
+}
+
+/// Runs each shell command in turn for `rounds` rounds and compares them
+async fn compare_commands(
+    commands: Vec<String>,
+    mut config: MeasurementConfig,
+    cgroup: bool,
+    rounds: usize,
+    warmup: usize,
+) -> Result<Comparison, MeasurementError> {
+    let (_cgroup, procs) = match cgroup.then(|| open_cgroup(&mut config)).flatten() {
+        Some((cgroup, procs)) => (Some(cgroup), Some(procs)),
+        None => (None, None),
+    };
+
+    let fifo = MarkerFifo::create_or_warn();
     let exec = BenchmarkExecutor::new(config);
-    exec.measure(move || {
-        let mut cmd = Command::new(&command[0]);
-        for arg in command.iter().skip(1) {
-            cmd.arg(arg);
+    let shells: Vec<Vec<String>> = commands
+        .iter()
+        .map(|command| vec!["sh".to_string(), "-c".to_string(), command.clone()])
+        .collect();
+    let mut measurements: Vec<Vec<EnergyMeasurement>> =
+        commands.iter().map(|_| Vec::new()).collect();
+    // Interleaved, so that drift in the system affects all commands alike
+    for round in 0..warmup + rounds {
+        for (shell, runs) in shells.iter().zip(&mut measurements) {
+            let measurement = measure_run(&exec, shell, procs.as_ref(), fifo.as_ref()).await?;
+            if round >= warmup {
+                runs.push(measurement);
+            }
+        }
+    }
+    let workloads = commands
+        .into_iter()
+        .zip(measurements)
+        .map(|(command, runs)| Ok((command, RepeatedMeasurement::new(runs, warmup)?)))
+        .collect::<Result<_, MeasurementError>>()?;
+    Comparison::new(workloads)
+}
+
+/// Quotes a CSV field when it contains a separator or quote
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// The power readings of each measurement as CSV, one column per domain,
+/// after the `labels` columns identifying the measurement
+fn format_samples(labels: &[&str], measurements: &[(Vec<String>, &EnergyMeasurement)]) -> String {
+    let mut domains: Vec<&str> = Vec::new();
+    for sample in measurements.iter().flat_map(|(_, m)| &m.samples) {
+        for domain in &sample.domains {
+            if !domains.contains(&domain.name.as_str()) {
+                domains.push(&domain.name);
+            }
+        }
+    }
+
+    let mut columns: Vec<String> = labels.iter().map(|label| label.to_string()).collect();
+    columns.extend(["offset_seconds".to_string(), "power_watts".to_string()]);
+    columns.extend(
+        domains
+            .iter()
+            .map(|domain| csv_field(&format!("{domain}_watts"))),
+    );
+    let mut output = columns.join(",");
+    for (values, measurement) in measurements {
+        for sample in &measurement.samples {
+            let mut row: Vec<String> = values.iter().map(|value| csv_field(value)).collect();
+            row.push(sample.offset.as_secs_f64().to_string());
+            row.push(sample.power.get::<watt>().to_string());
+            row.extend(domains.iter().map(|name| {
+                sample
+                    .domains
+                    .iter()
+                    .find(|domain| domain.name == *name)
+                    .map(|domain| domain.power.get::<watt>().to_string())
+                    .unwrap_or_default()
+            }));
+            output.push('\n');
+            output.push_str(&row.join(","));
+        }
+    }
+    output
+}
+
+fn format_comparison(comparison: &Comparison, format: Format, co2e_per_kwh: f64) -> String {
+    // Mean CO2e per run, of the net energy if `net` is set
+    let co2e = |workload: &ComparedWorkload, net: bool| {
+        let runs = &workload.measurement.runs;
+        runs.iter()
+            .map(|run| match net {
+                true => run.net_co2e(Some(co2e_per_kwh)),
+                false => run.co2e(Some(co2e_per_kwh)),
+            })
+            .sum::<f64>()
+            / runs.len() as f64
+    };
+    match format {
+        Format::Human => {
+            let reference = &comparison.workloads[comparison.reference];
+            let width = comparison
+                .workloads
+                .iter()
+                .map(|workload| workload.name.chars().count())
+                .max()
+                .unwrap_or(0)
+                .max("Command".len());
+            // A column for each figure all workloads have
+            let column = |header: &str, summary: fn(&RepeatedMeasurement) -> Option<&Summary>| {
+                let cells: Option<Vec<String>> = comparison
+                    .workloads
+                    .iter()
+                    .map(|workload| {
+                        let summary = summary(&workload.measurement)?;
+                        Some(format!("{:.3} ± {:.3}", summary.mean, summary.std_dev))
+                    })
+                    .collect();
+                cells.map(|cells| (header.to_string(), cells))
+            };
+            let columns: Vec<(String, Vec<String>)> = [
+                column("Energy (J)", |m| Some(&m.energy)),
+                column("Net energy (J)", |m| m.net_energy.as_ref()),
+                column("Attributed (J)", |m| m.attributed_energy.as_ref()),
+                column("Memory (J)", |m| m.memory_energy.as_ref()),
+                column("Network (J)", |m| m.network_energy.as_ref()),
+                column("Storage (J)", |m| m.storage_energy.as_ref()),
+            ]
+            .into_iter()
+            .flatten()
+            .map(|(header, cells)| {
+                let width = cells
+                    .iter()
+                    .chain([&header])
+                    .map(|cell| cell.chars().count())
+                    .max()
+                    .unwrap_or(0);
+                (
+                    format!("{:>width$}", header),
+                    cells
+                        .into_iter()
+                        .map(|cell| format!("{:>width$}", cell))
+                        .collect(),
+                )
+            })
+            .collect();
+
+            let headers: Vec<&str> = columns.iter().map(|(header, _)| header.as_str()).collect();
+            let mut output = format!(
+                "Energy Comparison ({} rounds, {} warmup, interleaved):\n\
+                 {:<width$}  {}  {:>8}  {:>10}  {:>8}",
+                reference.measurement.runs.len(),
+                reference.measurement.warmup,
+                "Command",
+                headers.join("  "),
+                "Relative",
+                "CO2e (g)",
+                "p-value",
+            );
+            for (index, workload) in comparison.workloads.iter().enumerate() {
+                let cells: Vec<&str> = columns
+                    .iter()
+                    .map(|(_, cells)| cells[index].as_str())
+                    .collect();
+                let p_value = match &workload.test {
+                    Some(test) => format!("{:.3}", test.p_value),
+                    None => "-".to_string(),
+                };
+                output.push_str(&format!(
+                    "\n{:<width$}  {}  {:>8.2}  {:>10.4}  {:>8}",
+                    workload.name,
+                    cells.join("  "),
+                    workload.relative_energy,
+                    co2e(workload, comparison.net_energy),
+                    p_value,
+                ));
+            }
+            output.push_str(&format!(
+                "\n\n`{}` used the least {}energy",
+                reference.name,
+                if comparison.net_energy { "net " } else { "" },
+            ));
+            for workload in &comparison.workloads {
+                let Some(test) = &workload.test else {
+                    continue;
+                };
+                output.push_str(&format!(
+                    "\n  {:.2}x less energy than `{}`{}",
+                    workload.relative_energy,
+                    workload.name,
+                    if test.significant {
+                        String::new()
+                    } else {
+                        format!(" (not significant at p < {})", SIGNIFICANCE_LEVEL)
+                    },
+                ));
+            }
+            output
+        }
+
+        Format::Samples => {
+            let rounds: Vec<(Vec<String>, &EnergyMeasurement)> = comparison
+                .workloads
+                .iter()
+                .flat_map(|workload| {
+                    workload
+                        .measurement
+                        .runs
+                        .iter()
+                        .enumerate()
+                        .map(|(round, run)| {
+                            (vec![workload.name.clone(), (round + 1).to_string()], run)
+                        })
+                })
+                .collect();
+            format_samples(&["command", "round"], &rounds)
+        }
+
+        Format::Json => {
+            let mut value = serde_json::to_value(comparison).unwrap();
+            for (index, workload) in comparison.workloads.iter().enumerate() {
+                value["workloads"][index]["co2e_grams"] = co2e(workload, false).into();
+                if comparison.net_energy {
+                    value["workloads"][index]["net_co2e_grams"] = co2e(workload, true).into();
+                }
+            }
+            serde_json::to_string_pretty(&value).unwrap()
+        }
+
+        Format::Csv => {
+            let mut output = "command,rounds,energy_joules,energy_std_dev_joules,power_watts,duration_seconds,relative_energy,co2e_grams,t_statistic,p_value,significant,net_energy_joules,net_energy_std_dev_joules,net_co2e_grams,attributed_energy_joules,memory_energy_joules,network_energy_joules,storage_energy_joules".to_string();
+            for workload in &comparison.workloads {
+                let measurement = &workload.measurement;
+                let mean = |summary: &Option<Summary>| {
+                    summary.map(|s| s.mean.to_string()).unwrap_or_default()
+                };
+                output.push_str(&format!(
+                    "\n{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                    csv_field(&workload.name),
+                    measurement.runs.len(),
+                    measurement.energy.mean,
+                    measurement.energy.std_dev,
+                    measurement.average_power.mean,
+                    measurement.duration.mean,
+                    workload.relative_energy,
+                    co2e(workload, false),
+                    workload.test.map(|t| t.t.to_string()).unwrap_or_default(),
+                    workload
+                        .test
+                        .map(|t| t.p_value.to_string())
+                        .unwrap_or_default(),
+                    workload
+                        .test
+                        .map(|t| t.significant.to_string())
+                        .unwrap_or_default(),
+                    mean(&measurement.net_energy),
+                    measurement
+                        .net_energy
+                        .map(|s| s.std_dev.to_string())
+                        .unwrap_or_default(),
+                    measurement
+                        .net_energy
+                        .map(|_| co2e(workload, true).to_string())
+                        .unwrap_or_default(),
+                    mean(&measurement.attributed_energy),
+                    mean(&measurement.memory_energy),
+                    mean(&measurement.network_energy),
+                    mean(&measurement.storage_energy),
+                ));
+            }
+            output
+        }
+    }
+}
+
+fn format_summary(name: &str, unit: &str, summary: &Summary) -> String {
+    format!(
+        "{name}: {:.3} {unit} ± {:.3} {unit}  (95% CI {:.3} {unit} … {:.3} {unit})\n  \
+         Median: {:.3} {unit}, range (min … max): {:.3} {unit} … {:.3} {unit}",
+        summary.mean,
+        summary.std_dev,
+        summary.ci_low,
+        summary.ci_high,
+        summary.median,
+        summary.min,
+        summary.max,
+    )
+}
+
+/// Name, CSV metric, unit and statistics of the figures only some
+/// measurements have, in the order a single measurement lists them
+fn optional_summaries(
+    repeated: &RepeatedMeasurement,
+) -> [(&'static str, &'static str, &'static str, Option<&Summary>); 6] {
+    [
+        (
+            "Attributed energy",
+            "attributed_energy_joules",
+            "J",
+            repeated.attributed_energy.as_ref(),
+        ),
+        (
+            "Memory energy",
+            "memory_energy_joules",
+            "J",
+            repeated.memory_energy.as_ref(),
+        ),
+        (
+            "Network energy",
+            "network_energy_joules",
+            "J",
+            repeated.network_energy.as_ref(),
+        ),
+        (
+            "Storage energy",
+            "storage_energy_joules",
+            "J",
+            repeated.storage_energy.as_ref(),
+        ),
+        (
+            "Baseline power",
+            "baseline_power_watts",
+            "W",
+            repeated.baseline_power.as_ref(),
+        ),
+        (
+            "Net energy",
+            "net_energy_joules",
+            "J",
+            repeated.net_energy.as_ref(),
+        ),
+    ]
+}
+
+fn format_repeated(repeated: &RepeatedMeasurement, format: Format, co2e_per_kwh: f64) -> String {
+    match format {
+        Format::Human => {
+            let co2e: Vec<f64> = repeated
+                .runs
+                .iter()
+                .map(|run| run.co2e(Some(co2e_per_kwh)))
+                .collect();
+            let mut output = format!(
+                "Energy Measurement Results ({} runs, {} warmup):\n{}\n{}\n{}\nCO2e: {:.2} {} per run\nMeasurement method: {}",
+                repeated.runs.len(),
+                repeated.warmup,
+                format_summary("Energy consumed", "J", &repeated.energy),
+                format_summary("Average power", "W", &repeated.average_power),
+                format_summary("Duration", "s", &repeated.duration),
+                co2e.iter().sum::<f64>() / co2e.len() as f64,
+                uom::si::mass::gram::plural(),
+                repeated.runs[0].measurement_method,
+            );
+            for (name, _, unit, summary) in optional_summaries(repeated) {
+                if let Some(summary) = summary {
+                    output.push('\n');
+                    output.push_str(&format_summary(name, unit, summary));
+                }
+            }
+            if repeated.net_energy.is_some() {
+                let net_co2e = repeated
+                    .runs
+                    .iter()
+                    .map(|run| run.net_co2e(Some(co2e_per_kwh)))
+                    .sum::<f64>()
+                    / repeated.runs.len() as f64;
+                output.push_str(&format!(
+                    "\nNet CO2e: {:.2} {} per run",
+                    net_co2e,
+                    uom::si::mass::gram::plural(),
+                ));
+            }
+            if !repeated.outliers.is_empty() {
+                let runs: Vec<String> = repeated
+                    .outliers
+                    .iter()
+                    .map(|index| (index + 1).to_string())
+                    .collect();
+                output.push_str(&format!(
+                    "\nWarning: Statistical outliers were detected in run {}. Consider \
+                     re-running on a quiet system, or with more --warmup runs.",
+                    runs.join(", ")
+                ));
+            }
+            output
+        }
+
+        Format::Json => serde_json::to_string_pretty(&repeated).unwrap(),
+
+        Format::Samples => {
+            let runs: Vec<(Vec<String>, &EnergyMeasurement)> = repeated
+                .runs
+                .iter()
+                .enumerate()
+                .map(|(run, measurement)| (vec![(run + 1).to_string()], measurement))
+                .collect();
+            format_samples(&["run"], &runs)
+        }
+
+        Format::Csv => {
+            let mut output = "metric,mean,median,std_dev,min,max,ci_low,ci_high".to_string();
+            let optional = optional_summaries(repeated)
+                .into_iter()
+                .filter_map(|(_, metric, _, summary)| Some((metric, summary?)));
+            for (metric, summary) in [
+                ("energy_joules", &repeated.energy),
+                ("power_watts", &repeated.average_power),
+                ("duration_seconds", &repeated.duration),
+            ]
+            .into_iter()
+            .chain(optional)
+            {
+                output.push_str(&format!(
+                    "\n{},{},{},{},{},{},{},{}",
+                    metric,
+                    summary.mean,
+                    summary.median,
+                    summary.std_dev,
+                    summary.min,
+                    summary.max,
+                    summary.ci_low,
+                    summary.ci_high,
+                ));
+            }
+            output
         }
-        cmd.status().expect("failed to execute process");
-    })
+    }
 }
 
 fn format_measurement(
@@ -86,42 +835,184 @@ fn format_measurement(
     co2e_per_kwh: f64,
 ) -> String {
     match format {
-        Format::Human => format!(
-            "Energy Measurement Results:\n\
-             Energy consumed: {:.2} {}  ({:.2} {})\n\
-             Average power: {:.2} {} \n\
-             Peak power: {:.2} {}\n\
-             Duration: {:.2} {}\n\
-             CO2e: {:.2} {}\n\
-             Measurement method: {}",
-            measurement.total_energy.get::<kilowatt_hour>(), uom::si::energy::kilowatt_hour::plural(),
-            measurement.total_energy.get::<joule>(), uom::si::energy::joule::plural(),
-            measurement.average_power.get::<watt>(), uom::si::power::watt::plural(),
-            measurement.peak_power.get::<watt>(), uom::si::power::watt::plural(),
-            measurement.duration.as_secs(), uom::si::time::second::plural(),
-            measurement.co2e(Some(co2e_per_kwh)), uom::si::mass::gram::plural(),
-            measurement.measurement_method,
-
+        Format::Human => {
+            let mut output = format!(
+                "Energy Measurement Results:\n\
+                 Energy consumed: {:.2} {}  ({:.2} {})\n\
+                 Average power: {:.2} {} \n\
+                 Peak power: {:.2} {}\n\
+                 Duration: {:.2} {}\n\
+                 CO2e: {:.2} {}\n\
+                 Measurement method: {}",
+                measurement.total_energy.get::<kilowatt_hour>(), uom::si::energy::kilowatt_hour::plural(),
+                measurement.total_energy.get::<joule>(), uom::si::energy::joule::plural(),
+                measurement.average_power.get::<watt>(), uom::si::power::watt::plural(),
+                measurement.peak_power.get::<watt>(), uom::si::power::watt::plural(),
+                measurement.duration.as_secs_f64(), uom::si::time::second::plural(),
+                measurement.co2e(Some(co2e_per_kwh)), uom::si::mass::gram::plural(),
+                measurement.measurement_method,
+            );
+            if let Some(profile) = &measurement.cpu_profile {
+                output.push_str(&format!("\nCPU profile: {}", profile));
+            }
+            for domain in &measurement.domains {
+                output.push_str(&format!(
+                    "\n  {}: {:.2} {} ({:.2} {})",
+                    domain.name,
+                    domain.energy.get::<joule>(), uom::si::energy::joule::plural(),
+                    domain.average_power.get::<watt>(), uom::si::power::watt::plural(),
+                ));
+            }
+            if let Some(attribution) = &measurement.attribution {
+                output.push_str(&format!(
+                    "\nAttributed energy: {:.2} {} ({:.1}% of CPU time, {:.2} {} CO2e)",
+                    attribution.attributed_energy.get::<joule>(), uom::si::energy::joule::plural(),
+                    attribution.cpu_share * 100.0,
+                    kwh_to_co2e(joules_to_kwh(attribution.attributed_energy), co2e_per_kwh),
+                    uom::si::mass::gram::plural(),
+                ));
+                if let Some(peak) = attribution.memory_peak_bytes {
+                    output.push_str(&format!("\nPeak memory: {:.1} MiB", peak as f64 / 1048576.0));
+                }
+                if let (Some(read), Some(written)) =
+                    (attribution.io_read_bytes, attribution.io_write_bytes)
+                {
+                    output.push_str(&format!("\nBlock I/O: {} bytes read, {} bytes written", read, written));
+                }
+            }
+            if let Some(memory) = &measurement.memory {
+                output.push_str(&format!(
+                    "\nMemory energy: {:.2} {} ({:.2} {}, {:.1} MiB average, {:.1} MiB peak)",
+                    memory.energy.get::<joule>(), uom::si::energy::joule::plural(),
+                    memory.average_power.get::<watt>(), uom::si::power::watt::plural(),
+                    memory.average_bytes as f64 / 1048576.0,
+                    memory.peak_bytes as f64 / 1048576.0,
+                ));
+                output.push_str(&format!(
+                    "\nCombined energy: {:.2} {}",
+                    measurement.combined_energy().get::<joule>(), uom::si::energy::joule::plural(),
+                ));
+            }
+            if let Some(network) = &measurement.network {
+                output.push_str(&format!(
+                    "\nNetwork transfer: {:.2} MB received, {:.2} MB sent ({})\n\
+                     Network energy: {:.2} {} ({:.2} {} CO2e)",
+                    network.received_bytes as f64 / 1e6,
+                    network.sent_bytes as f64 / 1e6,
+                    network.scope,
+                    network.energy.get::<joule>(), uom::si::energy::joule::plural(),
+                    network.co2e(Some(co2e_per_kwh)), uom::si::mass::gram::plural(),
+                ));
+            }
+            if let Some(storage) = &measurement.storage {
+                output.push_str(&format!(
+                    "\nStorage I/O: {:.2} MB read, {:.2} MB written ({}, {:.0}% rotational)\n\
+                     Storage energy: {:.2} {} ({:.2} {} CO2e)",
+                    storage.read_bytes as f64 / 1e6,
+                    storage.written_bytes as f64 / 1e6,
+                    storage.scope,
+                    storage.rotational_fraction * 100.0,
+                    storage.energy.get::<joule>(), uom::si::energy::joule::plural(),
+                    kwh_to_co2e(joules_to_kwh(storage.energy), co2e_per_kwh), uom::si::mass::gram::plural(),
+                ));
+            }
+            if let Some(baseline) = &measurement.baseline {
+                output.push_str(&format!(
+                    "\nBaseline power: {:.2} {} (idle {}, {} ms each)\n\
+                     Baseline energy: {:.2} {} ({:.2} {} CO2e)\n\
+                     Net energy: {:.2} {} ({:.2} {} CO2e)",
+                    baseline.power.get::<watt>(), uom::si::power::watt::plural(),
+                    baseline.method,
+                    baseline.period.as_millis(),
+                    baseline.energy.get::<joule>(), uom::si::energy::joule::plural(),
+                    kwh_to_co2e(joules_to_kwh(baseline.energy), co2e_per_kwh), uom::si::mass::gram::plural(),
+                    measurement.net_energy().get::<joule>(), uom::si::energy::joule::plural(),
+                    measurement.net_co2e(Some(co2e_per_kwh)), uom::si::mass::gram::plural(),
+                ));
+            }
+            if !measurement.power_supplies.is_empty() {
+                let supplies: Vec<String> = measurement
+                    .power_supplies
+                    .iter()
+                    .map(|supply| supply.to_string())
+                    .collect();
+                output.push_str(&format!("\nPower supplies: {}", supplies.join(", ")));
+            }
+            for phase in &measurement.phases {
+                output.push_str(&format!(
+                    "\nPhase {}: {:.2} {} ({:.2} {}, {:.2} {})",
+                    phase.name,
+                    phase.energy.get::<joule>(), uom::si::energy::joule::plural(),
+                    phase.average_power.get::<watt>(), uom::si::power::watt::plural(),
+                    phase.duration.as_secs_f64(), uom::si::time::second::plural(),
+                ));
+            }
+            for battery in &measurement.batteries {
+                output.push_str(&format!("\nBattery counter: {}", battery));
+            }
+            for warning in &measurement.warnings {
+                output.push_str(&format!("\nWarning: {}", warning));
+            }
+            output
+        }
 
-        ),
+        Format::Json => {
+            let mut value = serde_json::to_value(measurement).unwrap();
+            if let Some(network) = &measurement.network {
+                value["network"]["co2e_grams"] = network.co2e(Some(co2e_per_kwh)).into();
+            }
+            if let Some(storage) = &measurement.storage {
+                value["storage"]["co2e_grams"] =
+                    kwh_to_co2e(joules_to_kwh(storage.energy), co2e_per_kwh).into();
+            }
+            if measurement.baseline.is_some() {
+                value["baseline"]["net_co2e_grams"] =
+                    measurement.net_co2e(Some(co2e_per_kwh)).into();
+            }
+            serde_json::to_string_pretty(&value).unwrap()
+        }
 
-        Format::Json => serde_json::to_string_pretty(&measurement).unwrap(),
+        Format::Samples => format_samples(&[], &[(Vec::new(), measurement)]),
 
         Format::Csv => format!(
-            "energy_joules,energy_kwh,power_watts,peak_power_watts,duration_seconds,co2e_grams,measurement_method\n\
-             {},{},{},{},{},{},{}",
+            "energy_joules,energy_kwh,power_watts,peak_power_watts,duration_seconds,co2e_grams,measurement_method,attributed_energy_joules,cpu_share,memory_energy_joules,combined_energy_joules,network_bytes,network_energy_joules,network_co2e_grams,storage_bytes,storage_energy_joules,storage_co2e_grams,baseline_power_watts,baseline_energy_joules,net_energy_joules,net_co2e_grams\n\
+             {},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
             measurement.total_energy.get::<joule>(),
             measurement.total_energy.get::<kilowatt_hour>(),
             measurement.average_power.get::<watt>(),
             measurement.peak_power.get::<watt>(),
-            measurement.duration.as_secs(),
+            measurement.duration.as_secs_f64(),
             measurement.co2e(Some(co2e_per_kwh)),
             measurement.measurement_method,
-
+            measurement.attribution.as_ref().map(|a| a.attributed_energy.get::<joule>().to_string()).unwrap_or_default(),
+            measurement.attribution.as_ref().map(|a| a.cpu_share.to_string()).unwrap_or_default(),
+            measurement.memory.as_ref().map(|m| m.energy.get::<joule>().to_string()).unwrap_or_default(),
+            measurement.combined_energy().get::<joule>(),
+            measurement.network.as_ref().map(|n| (n.received_bytes + n.sent_bytes).to_string()).unwrap_or_default(),
+            measurement.network.as_ref().map(|n| n.energy.get::<joule>().to_string()).unwrap_or_default(),
+            measurement.network.as_ref().map(|n| n.co2e(Some(co2e_per_kwh)).to_string()).unwrap_or_default(),
+            measurement.storage.as_ref().map(|s| (s.read_bytes + s.written_bytes).to_string()).unwrap_or_default(),
+            measurement.storage.as_ref().map(|s| s.energy.get::<joule>().to_string()).unwrap_or_default(),
+            measurement.storage.as_ref().map(|s| kwh_to_co2e(joules_to_kwh(s.energy), co2e_per_kwh).to_string()).unwrap_or_default(),
+            measurement.baseline.as_ref().map(|b| b.power.get::<watt>().to_string()).unwrap_or_default(),
+            measurement.baseline.as_ref().map(|b| b.energy.get::<joule>().to_string()).unwrap_or_default(),
+            measurement.net_energy().get::<joule>(),
+            measurement.net_co2e(Some(co2e_per_kwh)),
         ),
     }
 }
 
+/// Prints each distinct warning of `measurements` to stderr
+fn print_warnings<'a>(measurements: impl IntoIterator<Item = &'a EnergyMeasurement>) {
+    let mut printed: Vec<&str> = Vec::new();
+    for warning in measurements.into_iter().flat_map(|m| &m.warnings) {
+        if !printed.contains(&warning.as_str()) {
+            eprintln!("Warning: {}", warning);
+            printed.push(warning);
+        }
+    }
+}
+
 #[okstd::main]
 async fn main() {
     let args: EnergyTool = argh::from_env();
@@ -133,15 +1024,84 @@ async fn main() {
 
     let config = MeasurementConfig {
         power_source: args.method,
-        duration: Duration::from_millis(args.duration),
+        min_duration: args.min_duration.map(Duration::from_millis),
+        max_duration: args.max_duration.map(Duration::from_millis),
         sample_interval_ms: args.interval,
+        tdp_watts: args.tdp,
+        idle_watts: args.idle_watts,
+        max_watts: args.max_watts,
+        utilization: args.utilization,
+        attribution: args.attribution,
+        // The command is spawned, so the tool's own sampling is left out
+        workload_processes: WorkloadProcesses::Children,
+        memory_watts_per_gb: args
+            .memory_watts_per_gb
+            .or(args.memory.then_some(DEFAULT_MEMORY_WATTS_PER_GB)),
+        network: args.network,
+        baseline: args.baseline.map(Duration::from_millis),
+        baseline_after: args.baseline_after,
+        storage: (args.storage
+            || args.ssd_joules_per_gb.is_some()
+            || args.hdd_joules_per_gb.is_some())
+        .then(|| {
+            let defaults = StorageCoefficients::default();
+            StorageCoefficients {
+                ssd_joules_per_gb: args.ssd_joules_per_gb.unwrap_or(defaults.ssd_joules_per_gb),
+                hdd_joules_per_gb: args.hdd_joules_per_gb.unwrap_or(defaults.hdd_joules_per_gb),
+            }
+        }),
+        ..Default::default()
     };
 
-    match measure_command(args.command, config).await {
+    if args.compare {
+        if args.command.len() < 2 {
+            eprintln!("--compare needs at least two commands");
+            std::process::exit(1);
+        }
+        let rounds = args.runs.unwrap_or(DEFAULT_COMPARE_ROUNDS);
+        if rounds < 2 {
+            eprintln!("--compare needs at least 2 rounds to test the difference");
+            std::process::exit(1);
+        }
+        match compare_commands(args.command, config, args.cgroup, rounds, args.warmup).await {
+            Ok(comparison) => {
+                print_warnings(
+                    comparison
+                        .workloads
+                        .iter()
+                        .flat_map(|workload| &workload.measurement.runs),
+                );
+                println!(
+                    "{}",
+                    format_comparison(&comparison, args.format, args.co2e_per_kwh)
+                );
+            }
+            Err(e) => {
+                eprintln!("Error comparing commands: {:?}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let runs = args.runs.unwrap_or(1);
+    let repeated = runs > 1 || args.warmup > 0;
+    match measure_command(args.command, config, args.cgroup, runs, args.warmup).await {
+        Ok(result) if repeated => {
+            print_warnings(&result.runs);
+            println!(
+                "{}",
+                format_repeated(&result, args.format, args.co2e_per_kwh)
+            );
+        }
         Ok(result) => {
+            // The human format lists them along with the results
+            if !matches!(args.format, Format::Human) {
+                print_warnings(&result.runs);
+            }
             println!(
                 "{}",
-                format_measurement(&result, args.format, args.co2e_per_kwh)
+                format_measurement(&result.runs[0], args.format, args.co2e_per_kwh)
             );
         }
         Err(e) => {
@@ -150,3 +1110,29 @@ async fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn test_marker_read_error() {
+        // The command runs to completion and keeps its status
+        let start = Instant::now();
+        let run = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            7
+        };
+        let read = async { Err(io::Error::other("broken pipe")) };
+        assert_eq!(run_reading(run, read).await, 7);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // An error after the command exited does not replace its status
+        let read = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Err(io::Error::other("broken pipe"))
+        };
+        assert_eq!(run_reading(async { 7 }, read).await, 7);
+    }
+}
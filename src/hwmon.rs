@@ -0,0 +1,347 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use uom::si::f64::{Energy, Power};
+use uom::si::{energy::microjoule, power::microwatt};
+
+use crate::{
+    counter_delta, read_sysfs_u64, DomainReading, MeasurementError, MeterCapabilities, PowerMeter,
+    PowerSource,
+};
+
+/// Chips whose power and energy sensors cover the CPU or SoC, rather than
+/// a GPU, charger or power rail
+const CPU_CHIPS: &[&str] = &[
+    "amd_energy",
+    "zenpower",
+    "k10temp",
+    "fam15h_power",
+    "xgene_hwmon",
+];
+
+/// A single hwmon `power*` or `energy*` sensor
+#[derive(Debug, Clone)]
+struct HwmonSensor {
+    /// Chip name followed by the sensor label, e.g. `amd_energy/Esocket0`
+    name: String,
+    /// Path to the `*_input` or `*_average` attribute
+    path: PathBuf,
+    /// Whether the sensor contributes to the summed total
+    counted: bool,
+}
+
+/// Power and energy sensors exposed through `/sys/class/hwmon`
+///
+/// Energy sensors (`energy*_input`, μJ) are preferred since they cover the
+/// whole run; power sensors (`power*_input` or `power*_average`, μW) are
+/// used when a machine has no energy sensors.
+pub struct HwmonMeasurement {
+    sensors: Vec<HwmonSensor>,
+    /// Whether `sensors` are energy counters rather than power readings
+    energy: bool,
+    last_counters: Vec<u64>,
+    /// Accumulated energy per sensor in microjoules
+    totals: Vec<u64>,
+}
+
+impl HwmonMeasurement {
+    /// Creates a new hwmon measurement instance
+    pub fn new() -> Result<Self, MeasurementError> {
+        Self::with_root("/")
+    }
+
+    /// Creates a new hwmon measurement instance reading `sys/class/hwmon`
+    /// below `root` instead of `/`
+    pub fn with_root(root: impl AsRef<Path>) -> Result<Self, MeasurementError> {
+        Self::open(root.as_ref(), |_| true)
+    }
+
+    /// Like [`HwmonMeasurement::with_root`], reading only the chips known
+    /// to measure the CPU or SoC, so that an automatic choice does not sum
+    /// in a GPU or charger
+    pub(crate) fn cpu_with_root(root: impl AsRef<Path>) -> Result<Self, MeasurementError> {
+        Self::open(root.as_ref(), |chip| CPU_CHIPS.contains(&chip))
+    }
+
+    fn open(root: &Path, include_chip: impl Fn(&str) -> bool) -> Result<Self, MeasurementError> {
+        let base_path = root.join("sys/class/hwmon");
+        let entries = fs::read_dir(&base_path).map_err(|_| MeasurementError::HwmonNotAvailable)?;
+
+        let mut chips: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+        chips.sort();
+
+        let mut energy_sensors = Vec::new();
+        let mut power_sensors = Vec::new();
+        for chip in chips {
+            let chip_name = fs::read_to_string(chip.join("name"))
+                .map(|name| name.trim().to_string())
+                .unwrap_or_else(|_| chip.file_name().unwrap().to_string_lossy().into_owned());
+            if !include_chip(&chip_name) {
+                continue;
+            }
+            energy_sensors.extend(chip_sensors(&chip, &chip_name, "energy", &["input"]));
+            power_sensors.extend(chip_sensors(
+                &chip,
+                &chip_name,
+                "power",
+                &["input", "average"],
+            ));
+        }
+
+        let energy = !energy_sensors.is_empty();
+        let mut sensors = if energy {
+            energy_sensors
+        } else {
+            power_sensors
+        };
+        if sensors.is_empty() {
+            return Err(MeasurementError::HwmonNotAvailable);
+        }
+
+        // Chips like amd_energy report every core next to the socket that
+        // contains them, so when socket sensors exist only they count
+        let is_socket = |sensor: &HwmonSensor| {
+            let name = sensor.name.to_lowercase();
+            name.contains("socket") || name.contains("package")
+        };
+        if sensors.iter().any(is_socket) {
+            for sensor in sensors.iter_mut() {
+                sensor.counted = is_socket(sensor);
+            }
+        }
+
+        let mut hwmon = Self {
+            totals: vec![0; sensors.len()],
+            sensors,
+            energy,
+            last_counters: Vec::new(),
+        };
+        if energy {
+            hwmon.last_counters = hwmon.read_values()?;
+        }
+        Ok(hwmon)
+    }
+
+    /// Names of the sensors this instance reads, in reporting order
+    pub fn domains(&self) -> impl Iterator<Item = &str> {
+        self.sensors.iter().map(|sensor| sensor.name.as_str())
+    }
+
+    fn read_values(&self) -> Result<Vec<u64>, MeasurementError> {
+        self.sensors
+            .iter()
+            .map(|sensor| read_sysfs_u64(&sensor.path))
+            .collect()
+    }
+}
+
+/// Finds the `<kind><N>_<attribute>` sensors of a chip, taking the first
+/// attribute in `attributes` that exists for each index
+fn chip_sensors(chip: &Path, chip_name: &str, kind: &str, attributes: &[&str]) -> Vec<HwmonSensor> {
+    let Ok(entries) = fs::read_dir(chip) else {
+        return Vec::new();
+    };
+
+    let mut indices: Vec<u32> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name();
+            let (index, _) = file_name.to_str()?.strip_prefix(kind)?.split_once('_')?;
+            index.parse().ok()
+        })
+        .collect();
+    indices.sort_unstable();
+    indices.dedup();
+
+    indices
+        .into_iter()
+        .filter_map(|index| {
+            let path = attributes
+                .iter()
+                .map(|attribute| chip.join(format!("{kind}{index}_{attribute}")))
+                .find(|path| fs::File::open(path).is_ok())?;
+            let label = fs::read_to_string(chip.join(format!("{kind}{index}_label")))
+                .map(|label| label.trim().to_string())
+                .unwrap_or_else(|_| format!("{kind}{index}"));
+            Some(HwmonSensor {
+                name: format!("{chip_name}/{label}"),
+                path,
+                counted: true,
+            })
+        })
+        .collect()
+}
+
+impl PowerMeter for HwmonMeasurement {
+    fn source(&self) -> PowerSource {
+        PowerSource::Hwmon
+    }
+
+    fn capabilities(&self) -> MeterCapabilities {
+        MeterCapabilities {
+            instantaneous_power: !self.energy,
+            cumulative_energy: self.energy,
+            power_resolution: (!self.energy).then(|| Power::new::<microwatt>(1.0)),
+            energy_resolution: self.energy.then(|| Energy::new::<microjoule>(1.0)),
+        }
+    }
+
+    fn read_power(&mut self) -> Result<Power, MeasurementError> {
+        if self.energy {
+            return Err(MeasurementError::Unsupported(
+                "instantaneous power".to_string(),
+            ));
+        }
+
+        let values = self.read_values()?;
+        let microwatts: u64 = self
+            .sensors
+            .iter()
+            .zip(values)
+            .filter(|(sensor, _)| sensor.counted)
+            .map(|(_, value)| value)
+            .sum();
+        Ok(Power::new::<microwatt>(microwatts as f64))
+    }
+
+    fn read_energy(&mut self) -> Result<Energy, MeasurementError> {
+        if !self.energy {
+            return Err(MeasurementError::Unsupported(
+                "cumulative energy".to_string(),
+            ));
+        }
+
+        let counters = self.read_values()?;
+        let mut total = 0;
+        for (i, sensor) in self.sensors.iter().enumerate() {
+            // hwmon does not publish a range, a smaller value means a reset
+            self.totals[i] += counter_delta(self.last_counters[i], counters[i], None);
+            if sensor.counted {
+                total += self.totals[i];
+            }
+        }
+        self.last_counters = counters;
+
+        Ok(Energy::new::<microjoule>(total as f64))
+    }
+
+    fn domains(&self) -> Vec<DomainReading> {
+        if !self.energy {
+            return Vec::new();
+        }
+
+        self.sensors
+            .iter()
+            .zip(&self.totals)
+            .map(|(sensor, total)| DomainReading {
+                name: sensor.name.clone(),
+                energy: Energy::new::<microjoule>(*total as f64),
+                included_in_total: sensor.counted,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::FakeRoot;
+    use uom::si::{energy::joule, power::watt};
+
+    #[test]
+    fn test_hwmon_energy_sockets() {
+        let root = FakeRoot::new("hwmon-amd");
+        let base = "sys/class/hwmon/hwmon2";
+        root.file(&format!("{base}/name"), "amd_energy\n")
+            .file(&format!("{base}/energy1_input"), "1000000\n")
+            .file(&format!("{base}/energy1_label"), "Ecore000\n")
+            .file(&format!("{base}/energy2_input"), "5000000\n")
+            .file(&format!("{base}/energy2_label"), "Esocket0\n")
+            // Temperature sensors on other chips are ignored
+            .file("sys/class/hwmon/hwmon0/name", "k10temp\n")
+            .file("sys/class/hwmon/hwmon0/temp1_input", "45000\n");
+
+        let mut hwmon = HwmonMeasurement::with_root(root.path()).unwrap();
+        assert_eq!(
+            hwmon.domains().collect::<Vec<_>>(),
+            ["amd_energy/Ecore000", "amd_energy/Esocket0"]
+        );
+        assert!(hwmon.capabilities().cumulative_energy);
+
+        root.file(&format!("{base}/energy1_input"), "1500000\n")
+            .file(&format!("{base}/energy2_input"), "7000000\n");
+        // Only the socket counts, the core is part of it
+        let energy = hwmon.read_energy().unwrap();
+        assert!((energy.get::<joule>() - 2.0).abs() < 1e-9);
+
+        let domains = PowerMeter::domains(&hwmon);
+        assert!(!domains[0].included_in_total);
+        assert!((domains[0].energy.get::<joule>() - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_hwmon_power_sensors() {
+        let root = FakeRoot::new("hwmon-arm");
+        let base = "sys/class/hwmon/hwmon0";
+        root.file(&format!("{base}/name"), "ina3221\n")
+            .file(&format!("{base}/power1_input"), "2500000\n")
+            .file(&format!("{base}/power1_label"), "VDD_CPU\n")
+            // Only an averaged reading for the second rail
+            .file(&format!("{base}/power2_average"), "1250000\n");
+
+        let mut hwmon = HwmonMeasurement::with_root(root.path()).unwrap();
+        assert_eq!(
+            hwmon.domains().collect::<Vec<_>>(),
+            ["ina3221/VDD_CPU", "ina3221/power2"]
+        );
+        assert!(!hwmon.capabilities().cumulative_energy);
+        let power = hwmon.read_power().unwrap();
+        assert!((power.get::<watt>() - 3.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_hwmon_cpu_chips() {
+        let root = FakeRoot::new("hwmon-cpu");
+        root.file("sys/class/hwmon/hwmon0/name", "amdgpu\n")
+            .file("sys/class/hwmon/hwmon0/power1_average", "80000000\n");
+        assert_eq!(
+            HwmonMeasurement::with_root(root.path())
+                .unwrap()
+                .sensors
+                .len(),
+            1
+        );
+        assert!(matches!(
+            HwmonMeasurement::cpu_with_root(root.path()),
+            Err(MeasurementError::HwmonNotAvailable)
+        ));
+
+        root.file("sys/class/hwmon/hwmon1/name", "zenpower\n")
+            .file("sys/class/hwmon/hwmon1/power1_input", "30000000\n")
+            .file("sys/class/hwmon/hwmon1/power1_label", "SVI2_P_Core\n");
+        let mut hwmon = HwmonMeasurement::cpu_with_root(root.path()).unwrap();
+        assert_eq!(
+            hwmon.domains().collect::<Vec<_>>(),
+            ["zenpower/SVI2_P_Core"]
+        );
+        assert!((hwmon.read_power().unwrap().get::<watt>() - 30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_hwmon_not_available() {
+        let root = FakeRoot::new("hwmon-none");
+        assert!(matches!(
+            HwmonMeasurement::with_root(root.path()),
+            Err(MeasurementError::HwmonNotAvailable)
+        ));
+
+        root.file("sys/class/hwmon/hwmon0/name", "nvme\n")
+            .file("sys/class/hwmon/hwmon0/temp1_input", "38850\n");
+        assert!(matches!(
+            HwmonMeasurement::with_root(root.path()),
+            Err(MeasurementError::HwmonNotAvailable)
+        ));
+    }
+}
@@ -4,19 +4,67 @@
 #![doc(html_favicon_url = "https://raw.githubusercontent.com/sevki/carbonara/main/carbonara.png")]
 
 use std::{
+    any::Any,
     fmt::Display,
     fs::{self, File},
+    future::{self, Future},
     io::{self, BufRead, BufReader},
-    path::Path,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    pin::pin,
     str::FromStr,
-    thread,
+    sync::{Arc, Mutex},
+    task::Poll,
     time::{Duration, Instant},
 };
 
 use serde::{Deserialize, Serialize};
 use uom::si::f64::Energy;
-use uom::si::{energy::joule, f64::Power};
 use uom::si::{energy::kilowatt_hour, power::watt};
+use uom::si::{
+    energy::{joule, microjoule},
+    f64::Power,
+    power::microwatt,
+};
+
+mod attribution;
+mod baseline;
+mod cgroup;
+mod cpu;
+mod hwmon;
+mod memory;
+mod meter;
+mod network;
+mod perf;
+mod phase;
+mod procfs;
+mod region;
+mod stats;
+mod storage;
+#[cfg(test)]
+mod testutil;
+
+use attribution::WorkloadTracker;
+pub use attribution::{Attribution, AttributionMode, WorkloadProcesses};
+use baseline::{measure_idle, measure_idle_async};
+pub use baseline::{Baseline, BaselineMethod};
+pub use cgroup::Cgroup;
+pub use cpu::{CpuPowerModel, CpuProfile, CpuProfileSource, UtilizationScope};
+pub use hwmon::HwmonMeasurement;
+use memory::MemoryModel;
+pub use memory::{MemoryEnergy, DEFAULT_MEMORY_WATTS_PER_GB};
+pub use meter::{DomainReading, MeterCapabilities, PowerMeter};
+use meter::{MeterError, MeterSlot, Sampler, SamplerHandle};
+use network::NetworkCounter;
+pub use network::{NetworkEnergy, NetworkScope};
+pub use perf::PerfRaplMeasurement;
+pub use phase::{Phase, PhaseMarkers};
+pub use region::{Region, RegionEnergy, RegionReport, Regions};
+pub use stats::{
+    ComparedWorkload, Comparison, RepeatedMeasurement, Summary, WelchTest, SIGNIFICANCE_LEVEL,
+};
+use storage::StorageCounter;
+pub use storage::{StorageCoefficients, StorageEnergy, StorageScope};
 
 /// Converts Gigabytes to kWh
 ///
@@ -120,10 +168,19 @@ pub enum PowerSource {
     Auto,
     /// Intel RAPL (Running Average Power Limit)
     Rapl,
+    /// Intel RAPL read through the perf_event `power` PMU
+    PerfRapl,
     /// System-wide power consumption via ACPI
     Acpi,
-    /// TDP-based estimation (least accurate)
+    /// Energy drawn from the ACPI battery counters while discharging
+    AcpiEnergy,
+    /// Power and energy sensors exposed through hwmon
+    Hwmon,
+    /// Estimation scaling CPU utilization between idle power and TDP
+    /// (least accurate)
     TdpEstimate,
+    /// A user-supplied [`PowerMeter`]
+    Custom,
 }
 
 impl Display for PowerSource {
@@ -131,8 +188,12 @@ impl Display for PowerSource {
         match self {
             PowerSource::Auto => write!(f, "Auto"),
             PowerSource::Rapl => write!(f, "RAPL"),
+            PowerSource::PerfRapl => write!(f, "perf RAPL"),
             PowerSource::Acpi => write!(f, "ACPI"),
+            PowerSource::AcpiEnergy => write!(f, "ACPI energy counter"),
+            PowerSource::Hwmon => write!(f, "hwmon"),
             PowerSource::TdpEstimate => write!(f, "TDP Estimate"),
+            PowerSource::Custom => write!(f, "Custom"),
         }
     }
 }
@@ -144,7 +205,10 @@ impl FromStr for PowerSource {
         match s {
             "auto" => Ok(PowerSource::Auto),
             "rapl" => Ok(PowerSource::Rapl),
+            "perf" => Ok(PowerSource::PerfRapl),
             "acpi" => Ok(PowerSource::Acpi),
+            "acpi-energy" => Ok(PowerSource::AcpiEnergy),
+            "hwmon" => Ok(PowerSource::Hwmon),
             "tdp" => Ok(PowerSource::TdpEstimate),
             _ => Err(format!("Unknown power source: {}", s)),
         }
@@ -154,12 +218,75 @@ impl FromStr for PowerSource {
 /// Measurement configuration
 #[derive(Debug)]
 pub struct MeasurementConfig {
-    /// Duration of the measurement
-    pub duration: Duration,
+    /// Keeps sampling for at least this long when the workload finishes
+    /// sooner, for meters that update slowly
+    pub min_duration: Option<Duration>,
+    /// Stops sampling after this long even if the workload is still
+    /// running; otherwise sampling lasts as long as the workload
+    pub max_duration: Option<Duration>,
     /// Preferred power source for measurements
     pub power_source: PowerSource,
     /// Sample interval in milliseconds
     pub sample_interval_ms: u64,
+    /// Filesystem root below which `sys` and `proc` are read, `/` unless
+    /// testing against a fake tree
+    pub fs_root: PathBuf,
+    /// CPU TDP in watts for the estimate; if unset it is looked up by CPU
+    /// model, falling back to 28 W (a laptop CPU)
+    pub tdp_watts: Option<f64>,
+    /// CPU power at idle for the estimate; if unset it is looked up by CPU
+    /// model, falling back to a fifth of `max_watts`
+    pub idle_watts: Option<f64>,
+    /// CPU power at full utilization for the estimate; if unset it is
+    /// looked up by CPU model, falling back to the TDP
+    pub max_watts: Option<f64>,
+    /// Which CPU time drives the utilization of the estimate
+    pub utilization: UtilizationScope,
+    /// How the measured system energy is attributed to the workload
+    pub attribution: AttributionMode,
+    /// Which processes the workload is when attributing, estimating
+    /// memory or counting I/O by process tree
+    pub workload_processes: WorkloadProcesses,
+    /// Enables estimating memory energy from the workload's resident
+    /// memory with this many watts per GB, e.g.
+    /// [`DEFAULT_MEMORY_WATTS_PER_GB`]
+    pub memory_watts_per_gb: Option<f64>,
+    /// Counts the bytes sent and received during the measurement and
+    /// estimates their energy with [`gigabytes_to_kwh`]
+    pub network: bool,
+    /// Enables estimating the energy of storage I/O with these
+    /// coefficients; the I/O of the workload is counted when attribution
+    /// is enabled and that of all disks otherwise
+    pub storage: Option<StorageCoefficients>,
+    /// Measures idle power for this long before the workload and reports
+    /// the energy net of it
+    pub baseline: Option<Duration>,
+    /// Measures idle power after the workload as well, averaging it with
+    /// the one before
+    pub baseline_after: bool,
+}
+
+impl Default for MeasurementConfig {
+    fn default() -> Self {
+        Self {
+            min_duration: None,
+            max_duration: None,
+            power_source: PowerSource::Auto,
+            sample_interval_ms: 100,
+            fs_root: PathBuf::from("/"),
+            tdp_watts: None,
+            idle_watts: None,
+            max_watts: None,
+            utilization: UtilizationScope::default(),
+            attribution: AttributionMode::default(),
+            workload_processes: WorkloadProcesses::default(),
+            memory_watts_per_gb: None,
+            network: false,
+            storage: None,
+            baseline: None,
+            baseline_after: false,
+        }
+    }
 }
 
 /// Measurement results
@@ -175,6 +302,91 @@ pub struct EnergyMeasurement {
     pub duration: Duration,
     /// Method used for measurement
     pub measurement_method: PowerSource,
+    /// Per-domain breakdown (e.g. RAPL packages and their subzones),
+    /// empty when the method has no domains
+    #[serde(default)]
+    pub domains: Vec<DomainEnergy>,
+    /// CPU power figures used by the TDP estimate
+    #[serde(default)]
+    pub cpu_profile: Option<CpuProfile>,
+    /// Share of `total_energy` attributed to the workload, when enabled in
+    /// [`MeasurementConfig::attribution`]
+    #[serde(default)]
+    pub attribution: Option<Attribution>,
+    /// Estimated energy of the workload's resident memory, when enabled in
+    /// [`MeasurementConfig::memory_watts_per_gb`]
+    #[serde(default)]
+    pub memory: Option<MemoryEnergy>,
+    /// Data transferred during the measurement, when enabled in
+    /// [`MeasurementConfig::network`]
+    #[serde(default)]
+    pub network: Option<NetworkEnergy>,
+    /// Data read from and written to storage during the measurement, when
+    /// enabled in [`MeasurementConfig::storage`]
+    #[serde(default)]
+    pub storage: Option<StorageEnergy>,
+    /// Idle power around the workload, when enabled in
+    /// [`MeasurementConfig::baseline`]
+    #[serde(default)]
+    pub baseline: Option<Baseline>,
+    /// Readings taken while the workload ran, in order
+    #[serde(default)]
+    pub samples: Vec<PowerSample>,
+    /// Energy between the markers set with [`MeterHandle::mark`], in order
+    #[serde(default)]
+    pub phases: Vec<Phase>,
+    /// Energy of the regions entered with [`MeterHandle::region`] or
+    /// [`BenchmarkExecutor::measure_async_with_regions`], as a tree of
+    /// top-level regions
+    #[serde(default)]
+    pub regions: Vec<RegionEnergy>,
+    /// Battery counters of a [`PowerSource::AcpiEnergy`] measurement
+    #[serde(default)]
+    pub batteries: Vec<BatteryCounter>,
+    /// Power supplies an ACPI measurement was read from
+    #[serde(default)]
+    pub power_supplies: Vec<PowerSupply>,
+    /// Problems that make the figures less reliable, such as counters that
+    /// updated too rarely
+    #[serde(default)]
+    pub warnings: Vec<String>,
+}
+
+/// Energy consumed by a single power domain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DomainEnergy {
+    /// Domain name, e.g. `package-0` or `package-0/dram`
+    pub name: String,
+    /// Energy consumed by the domain
+    pub energy: Energy,
+    /// Average power of the domain
+    pub average_power: Power,
+    /// Whether the domain is part of `total_energy`; subzones such as
+    /// `core` are already contained in their package
+    pub included_in_total: bool,
+}
+
+/// A single reading of the power meter during a measurement
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PowerSample {
+    /// Time since the start of the measurement
+    pub offset: Duration,
+    /// Power drawn at the time of the reading, or since the previous one
+    /// for meters that count energy
+    pub power: Power,
+    /// Power of each domain since the previous reading, empty when the
+    /// method has no domains
+    #[serde(default)]
+    pub domains: Vec<DomainPower>,
+}
+
+/// Power of a single domain in a [`PowerSample`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DomainPower {
+    /// Domain name, as in [`DomainEnergy::name`]
+    pub name: String,
+    /// Average power of the domain since the previous reading
+    pub power: Power,
 }
 
 impl Display for EnergyMeasurement {
@@ -187,11 +399,103 @@ impl Display for EnergyMeasurement {
             self.peak_power.get::<watt>(),
             self.duration,
             self.measurement_method
-        )
+        )?;
+        for domain in &self.domains {
+            write!(
+                f,
+                "\n  {}: {:.2} J ({:.2} W)",
+                domain.name,
+                domain.energy.get::<joule>(),
+                domain.average_power.get::<watt>()
+            )?;
+        }
+        if let Some(attribution) = &self.attribution {
+            write!(f, "\nAttributed: {}", attribution)?;
+        }
+        if let Some(memory) = &self.memory {
+            write!(
+                f,
+                "\nMemory: {:.2} J ({:.2} W)",
+                memory.energy.get::<joule>(),
+                memory.average_power.get::<watt>()
+            )?;
+        }
+        if let Some(network) = &self.network {
+            write!(
+                f,
+                "\nNetwork: {} bytes received, {} bytes sent, {:.2} J",
+                network.received_bytes,
+                network.sent_bytes,
+                network.energy.get::<joule>()
+            )?;
+        }
+        if let Some(storage) = &self.storage {
+            write!(
+                f,
+                "\nStorage: {} bytes read, {} bytes written, {:.2} J",
+                storage.read_bytes,
+                storage.written_bytes,
+                storage.energy.get::<joule>()
+            )?;
+        }
+        if let Some(baseline) = &self.baseline {
+            write!(
+                f,
+                "\nBaseline: {:.2} W idle, {:.2} J net",
+                baseline.power.get::<watt>(),
+                baseline.net_energy.get::<joule>()
+            )?;
+        }
+        for phase in &self.phases {
+            write!(
+                f,
+                "\nPhase {}: {:.2} J ({:.2} W, {:?})",
+                phase.name,
+                phase.energy.get::<joule>(),
+                phase.average_power.get::<watt>(),
+                phase.duration
+            )?;
+        }
+        if !self.regions.is_empty() {
+            write!(f, "\n{}", self.region_report())?;
+        }
+        if !self.power_supplies.is_empty() {
+            let supplies: Vec<String> = self.power_supplies.iter().map(|s| s.to_string()).collect();
+            write!(f, "\nPower supplies: {}", supplies.join(", "))?;
+        }
+        for battery in &self.batteries {
+            write!(f, "\nBattery: {}", battery)?;
+        }
+        for warning in &self.warnings {
+            write!(f, "\nWarning: {}", warning)?;
+        }
+        Ok(())
     }
 }
 
 impl EnergyMeasurement {
+    /// CPU energy of the workload plus its estimated memory energy
+    ///
+    /// The CPU part is the attributed energy if attribution is enabled and
+    /// the total otherwise. The memory estimate is left out when the total
+    /// already contains a measured DRAM domain.
+    pub fn combined_energy(&self) -> Energy {
+        let cpu = self
+            .attribution
+            .as_ref()
+            .map_or(self.total_energy, |attribution| {
+                attribution.attributed_energy
+            });
+        let measured_dram = self
+            .domains
+            .iter()
+            .any(|domain| domain.included_in_total && domain.name.ends_with("dram"));
+        match &self.memory {
+            Some(memory) if !measured_dram => cpu + memory.energy,
+            _ => cpu,
+        }
+    }
+
     /// Converts the total energy consumed to kWh
     pub fn co2e(&self, co2e_per_kwh: Option<f64>) -> f64 {
         kwh_to_co2e(
@@ -199,6 +503,31 @@ impl EnergyMeasurement {
             co2e_per_kwh.unwrap_or(436.0),
         )
     }
+
+    /// Total energy minus the idle baseline, or the total energy when no
+    /// baseline was measured
+    pub fn net_energy(&self) -> Energy {
+        self.baseline
+            .as_ref()
+            .map_or(self.total_energy, |baseline| baseline.net_energy)
+    }
+
+    /// Converts the net energy consumed to grams of CO2e
+    pub fn net_co2e(&self, co2e_per_kwh: Option<f64>) -> f64 {
+        kwh_to_co2e(
+            joules_to_kwh(self.net_energy()),
+            co2e_per_kwh.unwrap_or(436.0),
+        )
+    }
+
+    /// Inclusive and exclusive energy of each region as an indented table,
+    /// with each region's share of `total_energy`
+    pub fn region_report(&self) -> RegionReport<'_> {
+        RegionReport {
+            regions: &self.regions,
+            total_energy: self.total_energy,
+        }
+    }
 }
 
 /// ACPI power supply information
@@ -208,24 +537,101 @@ struct AcpiPowerInfo {
     current_now: f64,        // μA
     power_now: Option<f64>,  // μW
     energy_now: Option<f64>, // μWh
+    charge_now: Option<f64>, // μAh
+}
+
+/// Kind of an ACPI power supply, from its `type` file
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum PowerSupplyKind {
+    /// A battery powering the system
+    Battery,
+    /// An AC adapter
+    Mains,
+    /// A USB or USB Power Delivery source
+    Usb,
+}
+
+impl Display for PowerSupplyKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PowerSupplyKind::Battery => write!(f, "battery"),
+            PowerSupplyKind::Mains => write!(f, "mains"),
+            PowerSupplyKind::Usb => write!(f, "USB"),
+        }
+    }
+}
+
+/// A power supply an ACPI measurement was read from
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PowerSupply {
+    /// Name in `sys/class/power_supply`, e.g. `BAT0` or `ADP1`
+    pub name: String,
+    /// What kind of supply it is
+    pub kind: PowerSupplyKind,
+    /// Contents of its `status` file at the end of the measurement, e.g.
+    /// `Discharging`
+    pub status: Option<String>,
+}
+
+impl Display for PowerSupply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.status {
+            Some(status) => write!(f, "{} ({}, {})", self.name, self.kind, status),
+            None => write!(f, "{} ({})", self.name, self.kind),
+        }
+    }
+}
+
+/// Classifies the supply at `path`, or returns `None` for supplies that do
+/// not power the system or report nothing to measure
+fn classify_supply(path: &Path, name: &str) -> Option<PowerSupplyKind> {
+    let read = |file: &str| {
+        fs::read_to_string(path.join(file))
+            .ok()
+            .map(|content| content.trim().to_string())
+    };
+    // Batteries of mice, keyboards and other peripherals
+    if read("scope").as_deref() == Some("Device") {
+        return None;
+    }
+    let kind = match read("type").as_deref() {
+        Some("Battery") => PowerSupplyKind::Battery,
+        Some("Mains") => PowerSupplyKind::Mains,
+        Some(kind) if kind.starts_with("USB") => PowerSupplyKind::Usb,
+        Some(_) => return None,
+        // Without a type file, go by the usual ACPI names
+        None if name.starts_with("BAT") => PowerSupplyKind::Battery,
+        None if name.starts_with("AC") || name.starts_with("ADP") => PowerSupplyKind::Mains,
+        None => return None,
+    };
+    // Adapters usually expose nothing but `online`
+    let reports_power = path.join("power_now").exists()
+        || (path.join("voltage_now").exists() && path.join("current_now").exists());
+    (kind == PowerSupplyKind::Battery || reports_power).then_some(kind)
 }
 
 /// ACPI measurement implementation
 pub struct AcpiMeasurement {
-    power_supply_path: String,
-    cached_power_supplies: Vec<String>,
+    power_supply_path: PathBuf,
+    cached_power_supplies: Vec<(String, PowerSupplyKind)>,
 }
 
 impl AcpiMeasurement {
     /// Creates a new ACPI measurement instance
     pub fn new() -> Result<Self, MeasurementError> {
-        let base_path = "/sys/class/power_supply";
-        if !Path::new(base_path).exists() {
+        Self::with_root("/")
+    }
+
+    /// Creates a new ACPI measurement instance reading `sys/class/power_supply`
+    /// below `root` instead of `/`
+    pub fn with_root(root: impl AsRef<Path>) -> Result<Self, MeasurementError> {
+        let base_path = root.as_ref().join("sys/class/power_supply");
+        if !base_path.exists() {
             return Err(MeasurementError::AcpiNotAvailable);
         }
 
         // Find available power supplies
-        let entries = fs::read_dir(base_path).map_err(|_| MeasurementError::AcpiNotAvailable)?;
+        let entries = fs::read_dir(&base_path).map_err(|_| MeasurementError::AcpiNotAvailable)?;
 
         let mut power_supplies = Vec::new();
         for entry in entries.flatten() {
@@ -237,9 +643,8 @@ impl AcpiMeasurement {
                 let Some(name_str) = name.to_str() else {
                     continue;
                 };
-                // Only include BAT* and AC* devices
-                if name_str.starts_with("BAT") || name_str.starts_with("AC") {
-                    power_supplies.push(name_str.to_string());
+                if let Some(kind) = classify_supply(&path, name_str) {
+                    power_supplies.push((name_str.to_string(), kind));
                 }
             }
         }
@@ -247,23 +652,31 @@ impl AcpiMeasurement {
         if power_supplies.is_empty() {
             return Err(MeasurementError::AcpiNotAvailable);
         }
+        power_supplies.sort_by(|a, b| a.0.cmp(&b.0));
 
         Ok(Self {
-            power_supply_path: base_path.to_string(),
+            power_supply_path: base_path,
             cached_power_supplies: power_supplies,
         })
     }
 
+    /// The supplies this instance reads
+    pub fn power_supplies(&self) -> impl Iterator<Item = (&str, PowerSupplyKind)> {
+        self.cached_power_supplies
+            .iter()
+            .map(|(name, kind)| (name.as_str(), *kind))
+    }
+
     fn read_power_info(&self) -> Result<Vec<AcpiPowerInfo>, MeasurementError> {
         let mut results = Vec::new();
 
-        for supply in &self.cached_power_supplies {
-            let base_path = format!("{}/{}", self.power_supply_path, supply);
+        for (supply, _) in &self.cached_power_supplies {
+            let base_path = self.power_supply_path.join(supply);
 
             // Helper function to read numeric value from ACPI file
             let read_value = |filename: &str| -> Result<Option<f64>, MeasurementError> {
-                let path = format!("{}/{}", base_path, filename);
-                if !Path::new(&path).exists() {
+                let path = base_path.join(filename);
+                if !path.exists() {
                     return Ok(None);
                 }
 
@@ -282,12 +695,14 @@ impl AcpiMeasurement {
             let current = read_value("current_now")?.unwrap_or(0.0);
             let power = read_value("power_now")?;
             let energy = read_value("energy_now")?;
+            let charge = read_value("charge_now")?;
 
             results.push(AcpiPowerInfo {
                 voltage_now: voltage,
                 current_now: current,
                 power_now: power,
                 energy_now: energy,
+                charge_now: charge,
             });
         }
 
@@ -295,21 +710,309 @@ impl AcpiMeasurement {
     }
 
     fn calculate_power(&self, info: &[AcpiPowerInfo]) -> f64 {
-        let mut total_power = 0.0;
-
-        for supply in info {
-            // If power_now is available, use it directly
-            if let Some(power) = supply.power_now {
-                total_power += power;
-            } else {
-                // Otherwise calculate from voltage and current
-                total_power += (supply.voltage_now * supply.current_now) / 1_000_000.0;
-                // Convert to Watts
+        let mut battery_power = 0.0;
+        let mut external_power = 0.0;
+
+        for ((_, kind), supply) in self.cached_power_supplies.iter().zip(info) {
+            // If power_now is available, use it directly, otherwise
+            // calculate it from voltage and current
+            let power = supply
+                .power_now
+                .unwrap_or(supply.voltage_now * supply.current_now / 1_000_000.0);
+            match kind {
+                PowerSupplyKind::Battery => battery_power += power,
+                _ => external_power += power,
             }
         }
 
+        // External supplies that report their output cover the whole draw,
+        // including any charging of the batteries
+        let total_power = if external_power > 0.0 {
+            external_power
+        } else {
+            battery_power
+        };
         total_power / 1_000_000.0 // Convert μW to W
     }
+
+    /// Lists the supplies with their current status in `measurement`,
+    /// warning when a battery the power is read from is charging
+    fn annotate_supplies(&self, measurement: &mut EnergyMeasurement) {
+        let external = self
+            .cached_power_supplies
+            .iter()
+            .any(|(_, kind)| *kind != PowerSupplyKind::Battery);
+        for (name, kind) in &self.cached_power_supplies {
+            let status = fs::read_to_string(self.power_supply_path.join(name).join("status"))
+                .ok()
+                .map(|status| status.trim().to_string());
+            if *kind == PowerSupplyKind::Battery
+                && status.as_deref() == Some("Charging")
+                && (!external || measurement.measurement_method == PowerSource::AcpiEnergy)
+            {
+                measurement.warnings.push(format!(
+                    "{} is charging, so its readings reflect the charge rate rather than \
+                     the system's draw",
+                    name
+                ));
+            }
+            measurement.power_supplies.push(PowerSupply {
+                name: name.clone(),
+                kind: *kind,
+                status,
+            });
+        }
+    }
+}
+
+impl PowerMeter for AcpiMeasurement {
+    fn source(&self) -> PowerSource {
+        PowerSource::Acpi
+    }
+
+    fn capabilities(&self) -> MeterCapabilities {
+        MeterCapabilities {
+            instantaneous_power: true,
+            cumulative_energy: false,
+            // power_now is reported in μW
+            power_resolution: Some(Power::new::<microwatt>(1.0)),
+            energy_resolution: None,
+        }
+    }
+
+    fn read_power(&mut self) -> Result<Power, MeasurementError> {
+        let info = self.read_power_info()?;
+        Ok(Power::new::<watt>(self.calculate_power(&info)))
+    }
+
+    fn annotate(&self, measurement: &mut EnergyMeasurement) {
+        self.annotate_supplies(measurement);
+    }
+}
+
+/// Battery counter updates below which the energy of a
+/// [`PowerSource::AcpiEnergy`] measurement is flagged as coarse
+const MIN_BATTERY_UPDATES: usize = 10;
+
+/// Quantity a battery's remaining energy is read from
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum BatteryQuantity {
+    /// `energy_now` in μWh
+    EnergyNow,
+    /// `charge_now` in μAh, converted with `voltage_now`
+    ChargeNow,
+}
+
+impl Display for BatteryQuantity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BatteryQuantity::EnergyNow => write!(f, "energy_now"),
+            BatteryQuantity::ChargeNow => write!(f, "charge_now × voltage_now"),
+        }
+    }
+}
+
+/// A battery counter read by a [`PowerSource::AcpiEnergy`] measurement;
+/// its energy is reported as the domain of the same name
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatteryCounter {
+    /// Power supply name, e.g. `BAT0`
+    pub supply: String,
+    /// Quantity the energy was read from
+    pub quantity: BatteryQuantity,
+    /// Number of times the counter changed during the measurement
+    pub updates: usize,
+    /// Average time between changes, when it changed more than once
+    pub update_interval: Option<Duration>,
+}
+
+impl Display for BatteryCounter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} from {}, {} updates",
+            self.supply, self.quantity, self.updates
+        )?;
+        if let Some(interval) = self.update_interval {
+            write!(f, " every {:.1} s", interval.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
+
+/// Discharge of a single battery since the meter was created
+struct BatteryState {
+    /// Index of the supply in the ACPI readings
+    index: usize,
+    name: String,
+    quantity: BatteryQuantity,
+    /// Last `energy_now` or `charge_now` read
+    last_value: f64,
+    /// Last `voltage_now` read, in μV
+    last_voltage: f64,
+    discharged: Energy,
+}
+
+/// ACPI measurement from the drop of the batteries' remaining energy
+///
+/// Only decreases of the counters are counted, so nothing is measured
+/// while the batteries charge. Firmware typically refreshes the counters
+/// every few seconds, which makes this suited to long runs only.
+pub struct AcpiEnergyMeasurement {
+    acpi: AcpiMeasurement,
+    batteries: Vec<BatteryState>,
+}
+
+impl AcpiEnergyMeasurement {
+    /// Creates a new ACPI energy counter instance
+    pub fn new() -> Result<Self, MeasurementError> {
+        Self::with_root("/")
+    }
+
+    /// Creates a new ACPI energy counter instance reading
+    /// `sys/class/power_supply` below `root` instead of `/`
+    pub fn with_root(root: impl AsRef<Path>) -> Result<Self, MeasurementError> {
+        let acpi = AcpiMeasurement::with_root(root)?;
+        let info = acpi.read_power_info()?;
+        let batteries: Vec<BatteryState> = acpi
+            .cached_power_supplies
+            .iter()
+            .zip(&info)
+            .enumerate()
+            .filter(|(_, ((_, kind), _))| *kind == PowerSupplyKind::Battery)
+            .filter_map(|(index, ((name, _), info))| {
+                // energy_now is exact, while charge_now drifts with voltage
+                let (quantity, value) = match (info.energy_now, info.charge_now) {
+                    (Some(energy), _) => (BatteryQuantity::EnergyNow, energy),
+                    (None, Some(charge)) if info.voltage_now > 0.0 => {
+                        (BatteryQuantity::ChargeNow, charge)
+                    }
+                    _ => return None,
+                };
+                Some(BatteryState {
+                    index,
+                    name: name.clone(),
+                    quantity,
+                    last_value: value,
+                    last_voltage: info.voltage_now,
+                    discharged: Energy::new::<joule>(0.0),
+                })
+            })
+            .collect();
+        if batteries.is_empty() {
+            return Err(MeasurementError::AcpiNotAvailable);
+        }
+        Ok(Self { acpi, batteries })
+    }
+}
+
+impl PowerMeter for AcpiEnergyMeasurement {
+    fn source(&self) -> PowerSource {
+        PowerSource::AcpiEnergy
+    }
+
+    fn capabilities(&self) -> MeterCapabilities {
+        MeterCapabilities {
+            instantaneous_power: false,
+            cumulative_energy: true,
+            power_resolution: None,
+            // energy_now is reported in μWh
+            energy_resolution: Some(Energy::new::<joule>(0.0036)),
+        }
+    }
+
+    fn read_energy(&mut self) -> Result<Energy, MeasurementError> {
+        let info = self.acpi.read_power_info()?;
+        for battery in &mut self.batteries {
+            let info = &info[battery.index];
+            let value = match battery.quantity {
+                BatteryQuantity::EnergyNow => info.energy_now,
+                BatteryQuantity::ChargeNow => info.charge_now,
+            }
+            .ok_or_else(|| {
+                MeasurementError::InvalidMeasurement(format!(
+                    "{} no longer reports {}",
+                    battery.name, battery.quantity
+                ))
+            })?;
+            let drop = battery.last_value - value;
+            if drop > 0.0 {
+                let microwatt_hours = match battery.quantity {
+                    BatteryQuantity::EnergyNow => drop,
+                    // The charge drained at the average voltage in between
+                    BatteryQuantity::ChargeNow => {
+                        drop * (battery.last_voltage + info.voltage_now) / 2.0 / 1_000_000.0
+                    }
+                };
+                battery.discharged += Energy::new::<joule>(microwatt_hours * 0.0036);
+            }
+            battery.last_value = value;
+            battery.last_voltage = info.voltage_now;
+        }
+        Ok(self
+            .batteries
+            .iter()
+            .map(|battery| battery.discharged)
+            .sum())
+    }
+
+    fn domains(&self) -> Vec<DomainReading> {
+        self.batteries
+            .iter()
+            .map(|battery| DomainReading {
+                name: battery.name.clone(),
+                energy: battery.discharged,
+                included_in_total: true,
+            })
+            .collect()
+    }
+
+    fn annotate(&self, measurement: &mut EnergyMeasurement) {
+        self.acpi.annotate_supplies(measurement);
+        let seconds = measurement.duration.as_secs_f64();
+        for battery in &self.batteries {
+            // Readings during which the counter dropped
+            let updates: Vec<Duration> = measurement
+                .samples
+                .iter()
+                .filter(|sample| {
+                    sample.domains.iter().any(|domain| {
+                        domain.name == battery.name && domain.power.get::<watt>() > 0.0
+                    })
+                })
+                .map(|sample| sample.offset)
+                .collect();
+            let update_interval = match updates.as_slice() {
+                [first, .., last] => Some((*last - *first) / (updates.len() - 1) as u32),
+                _ => None,
+            };
+            let counter = BatteryCounter {
+                supply: battery.name.clone(),
+                quantity: battery.quantity,
+                updates: updates.len(),
+                update_interval,
+            };
+            match counter.updates {
+                0 => measurement.warnings.push(format!(
+                    "{} {} did not change during the {:.1} s measurement; it updates too \
+                     rarely for a run this short, or the battery is not discharging",
+                    counter.supply, battery.quantity, seconds
+                )),
+                updates if updates < MIN_BATTERY_UPDATES => measurement.warnings.push(format!(
+                    "{} {} changed only {}, so the energy may be off by up to one update; \
+                     measure for longer",
+                    counter.supply,
+                    battery.quantity,
+                    match updates {
+                        1 => "once".to_string(),
+                        updates => format!("{updates} times"),
+                    }
+                )),
+                _ => {}
+            }
+            measurement.batteries.push(counter);
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -321,8 +1024,16 @@ pub enum MeasurementError {
     RaplNotAvailable,
     /// ACPI not available
     AcpiNotAvailable,
+    /// No hwmon power or energy sensors available
+    HwmonNotAvailable,
+    /// The perf_event `power` PMU is missing or not permitted
+    PerfNotAvailable,
     /// Invalid measurement data
     InvalidMeasurement(String),
+    /// The power meter does not support the requested reading
+    Unsupported(String),
+    /// The workload panicked with this message
+    WorkloadPanicked(String),
 }
 impl From<io::Error> for MeasurementError {
     fn from(error: io::Error) -> Self {
@@ -330,212 +1041,758 @@ impl From<io::Error> for MeasurementError {
     }
 }
 
+/// A single RAPL power zone, either a package or one of its subzones
+#[derive(Debug, Clone)]
+struct RaplZone {
+    /// Domain name, e.g. `package-0` or `package-0/dram`
+    name: String,
+    /// Path to the zone's `energy_uj` counter
+    energy_path: PathBuf,
+    /// Value at which `energy_uj` wraps back to zero
+    max_energy_range: Option<u64>,
+    /// Whether the zone contributes to the summed total
+    counted: bool,
+}
+
 /// Intel RAPL measurement implementation
 pub struct RaplMeasurement {
-    package_path: String,
+    zones: Vec<RaplZone>,
+    last_counters: Vec<u64>,
+    /// Accumulated energy per zone in microjoules
+    totals: Vec<u64>,
 }
 
 impl RaplMeasurement {
     /// Create a new RAPL measurement instance
     pub fn new() -> Result<Self, MeasurementError> {
+        Self::with_root("/")
+    }
+
+    /// Create a new RAPL measurement instance reading `sys/class/powercap`
+    /// below `root` instead of `/`
+    pub fn with_root(root: impl AsRef<Path>) -> Result<Self, MeasurementError> {
         // Check if RAPL is available
-        let package_path = "/sys/class/powercap/intel-rapl/intel-rapl:0/energy_uj";
-        if !std::path::Path::new(package_path).exists() {
+        let base_path = root.as_ref().join("sys/class/powercap/intel-rapl");
+        if !base_path.exists() {
             return Err(MeasurementError::RaplNotAvailable);
         }
-        // check if we have permission to read the file
-        if File::open(package_path).is_err() {
+
+        let mut zones = Vec::new();
+        for package in rapl_zone_dirs(&base_path) {
+            let Some(package_name) = read_zone_name(&package) else {
+                continue;
+            };
+            zones.extend(rapl_zone(&package, package_name.clone(), None));
+
+            // Subzones (core, uncore, dram) live below their package
+            for subzone in rapl_zone_dirs(&package) {
+                let Some(name) = read_zone_name(&subzone) else {
+                    continue;
+                };
+                zones.extend(rapl_zone(&subzone, name, Some(&package_name)));
+            }
+        }
+
+        // psys covers the whole platform and would double count the
+        // packages, so it only counts when nothing else does
+        if !zones.iter().any(|zone| zone.counted) {
+            for zone in zones.iter_mut().filter(|zone| zone.name == "psys") {
+                zone.counted = true;
+            }
+        }
+
+        if !zones.iter().any(|zone| zone.counted) {
             return Err(MeasurementError::RaplNotAvailable);
+        }
+
+        let mut rapl = Self {
+            totals: vec![0; zones.len()],
+            zones,
+            last_counters: Vec::new(),
         };
-        Ok(Self {
-            package_path: package_path.to_string(),
+        rapl.last_counters = rapl.read_energy_counters()?;
+        Ok(rapl)
+    }
+
+    /// Names of the RAPL domains this instance reads, in reporting order
+    pub fn domains(&self) -> impl Iterator<Item = &str> {
+        self.zones.iter().map(|zone| zone.name.as_str())
+    }
+
+    fn read_energy_counters(&self) -> Result<Vec<u64>, MeasurementError> {
+        self.zones
+            .iter()
+            .map(|zone| read_sysfs_u64(&zone.energy_path))
+            .collect()
+    }
+}
+
+/// Lists the `intel-rapl:*` zone directories directly below `path`
+fn rapl_zone_dirs(path: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(path) else {
+        return Vec::new();
+    };
+
+    let mut dirs: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("intel-rapl:"))
         })
+        .collect();
+    dirs.sort();
+    dirs
+}
+
+fn read_zone_name(zone: &Path) -> Option<String> {
+    let name = fs::read_to_string(zone.join("name")).ok()?;
+    Some(name.trim().to_string())
+}
+
+/// Builds a zone if its counter is readable; packages and dram count
+/// towards the total, since dram energy is not part of the package domain
+fn rapl_zone(path: &Path, name: String, package: Option<&str>) -> Option<RaplZone> {
+    let energy_path = path.join("energy_uj");
+    // check if we have permission to read the file
+    File::open(&energy_path).ok()?;
+
+    let counted = match package {
+        None => name.starts_with("package"),
+        Some(_) => name == "dram",
+    };
+    let name = match package {
+        Some(package) => format!("{}/{}", package, name),
+        None => name,
+    };
+
+    let max_energy_range = read_sysfs_u64(&path.join("max_energy_range_uj")).ok();
+
+    Some(RaplZone {
+        name,
+        energy_path,
+        max_energy_range,
+        counted,
+    })
+}
+
+/// Reads an integer sysfs attribute such as `energy_uj`
+pub(crate) fn read_sysfs_u64(path: &Path) -> Result<u64, MeasurementError> {
+    let file = File::open(path)?;
+    let mut reader = BufReader::new(file);
+    let mut value = String::new();
+    reader.read_line(&mut value)?;
+    value
+        .trim()
+        .parse::<u64>()
+        .map_err(|e| MeasurementError::InvalidMeasurement(e.to_string()))
+}
+
+/// Difference between two `energy_uj` readings, corrected for the counter
+/// wrapping past `max_energy_range_uj`
+pub(crate) fn counter_delta(start: u64, end: u64, max_energy_range: Option<u64>) -> u64 {
+    if end >= start {
+        return end - start;
+    }
+    match max_energy_range {
+        Some(max) if start <= max => max - start + end,
+        // Without a known range the best we can do is count from zero
+        _ => end,
+    }
+}
+
+impl PowerMeter for RaplMeasurement {
+    fn source(&self) -> PowerSource {
+        PowerSource::Rapl
     }
 
-    fn read_energy_counter(&self) -> Result<u64, MeasurementError> {
-        let file = File::open(&self.package_path)?;
-        let mut reader = BufReader::new(file);
-        let mut value = String::new();
-        reader.read_line(&mut value)?;
-        value
-            .trim()
-            .parse::<u64>()
-            .map_err(|e| MeasurementError::InvalidMeasurement(e.to_string()))
+    fn capabilities(&self) -> MeterCapabilities {
+        MeterCapabilities {
+            instantaneous_power: false,
+            cumulative_energy: true,
+            power_resolution: None,
+            // energy_uj is reported in μJ
+            energy_resolution: Some(Energy::new::<microjoule>(1.0)),
+        }
+    }
+
+    fn read_energy(&mut self) -> Result<Energy, MeasurementError> {
+        let counters = self.read_energy_counters()?;
+
+        let mut total = 0;
+        for (i, zone) in self.zones.iter().enumerate() {
+            self.totals[i] +=
+                counter_delta(self.last_counters[i], counters[i], zone.max_energy_range);
+            if zone.counted {
+                total += self.totals[i];
+            }
+        }
+        self.last_counters = counters;
+
+        Ok(Energy::new::<microjoule>(total as f64))
+    }
+
+    fn domains(&self) -> Vec<DomainReading> {
+        self.zones
+            .iter()
+            .zip(&self.totals)
+            .map(|(zone, total)| DomainReading {
+                name: zone.name.clone(),
+                energy: Energy::new::<microjoule>(*total as f64),
+                included_in_total: zone.counted,
+            })
+            .collect()
+    }
+}
+
+/// Estimates power from a fixed TDP (Thermal Design Power)
+pub struct TdpEstimator {
+    tdp: Power,
+    created: Instant,
+    profile: Option<CpuProfile>,
+}
+
+impl TdpEstimator {
+    /// Create an estimator that assumes a constant draw of `tdp_watts`
+    pub fn new(tdp_watts: f64) -> Self {
+        Self {
+            tdp: Power::new::<watt>(tdp_watts),
+            created: Instant::now(),
+            profile: None,
+        }
+    }
+
+    /// Reports `profile` as the origin of the TDP
+    pub fn with_profile(mut self, profile: CpuProfile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+}
+
+impl PowerMeter for TdpEstimator {
+    fn source(&self) -> PowerSource {
+        PowerSource::TdpEstimate
+    }
+
+    fn capabilities(&self) -> MeterCapabilities {
+        MeterCapabilities {
+            instantaneous_power: true,
+            cumulative_energy: true,
+            power_resolution: None,
+            energy_resolution: None,
+        }
+    }
+
+    fn read_power(&mut self) -> Result<Power, MeasurementError> {
+        Ok(self.tdp)
+    }
+
+    fn read_energy(&mut self) -> Result<Energy, MeasurementError> {
+        Ok(tdp_to_joules(
+            self.tdp.get::<watt>(),
+            self.created.elapsed().as_secs_f64(),
+        ))
+    }
+
+    fn annotate(&self, measurement: &mut EnergyMeasurement) {
+        measurement.cpu_profile = self.profile.clone();
+    }
+}
+
+/// A measurement in progress, started by [`BenchmarkExecutor::start`]
+///
+/// Dropping the handle without calling [`MeterHandle::stop`] discards the
+/// measurement and hands the meter back to the executor.
+pub struct MeterHandle<'a> {
+    executor: &'a BenchmarkExecutor,
+    /// Taken by `stop`, or by `drop` if the handle was not stopped
+    sampler: Option<SamplerHandle>,
+    /// Length and idle power of the phase before the workload
+    before: Option<(Duration, Power)>,
+    markers: PhaseMarkers,
+    regions: Regions,
+}
+
+impl MeterHandle<'_> {
+    /// Ends the current phase and starts one called `name`, reported in
+    /// [`EnergyMeasurement::phases`]
+    pub fn mark(&self, name: impl Into<String>) {
+        self.markers.mark(name);
+    }
+
+    /// Markers of this measurement that can be moved to other threads
+    pub fn markers(&self) -> PhaseMarkers {
+        self.markers.clone()
+    }
+
+    /// Enters a top-level region called `name`, reported in
+    /// [`EnergyMeasurement::regions`] once the returned [`Region`] is
+    /// dropped
+    pub fn region(&self, name: impl Into<String>) -> Region {
+        self.regions.region(name)
+    }
+
+    /// Regions of this measurement that can be moved to other threads
+    pub fn regions(&self) -> Regions {
+        self.regions.clone()
+    }
+
+    /// Stops measuring and returns the measurement
+    pub fn stop(mut self) -> Result<EnergyMeasurement, MeasurementError> {
+        let Some(sampler) = self.sampler.take() else {
+            unreachable!("the sampler is only taken by stop and drop");
+        };
+        let (mut measurement, mut meter) = self.executor.recover(sampler.stop())?.finish();
+
+        // Idle phase after the workload
+        let after = match (self.before, self.executor.config.baseline_after) {
+            (Some((period, _)), true) => {
+                let interval = self.executor.sample_interval();
+                let (power, idle_meter) = self
+                    .executor
+                    .recover(measure_idle(meter, interval, period))?;
+                meter = idle_meter;
+                Some(power)
+            }
+            _ => None,
+        };
+        record_baseline(&mut measurement, self.before, after);
+
+        self.executor.return_meter(meter);
+        Ok(measurement)
+    }
+}
+
+impl Drop for MeterHandle<'_> {
+    fn drop(&mut self) {
+        if let Some(sampler) = self.sampler.take() {
+            if let Ok(sampler) = self.executor.recover(sampler.stop()) {
+                let (_, meter) = sampler.finish();
+                self.executor.return_meter(meter);
+            }
+        }
     }
 }
 
 /// Benchmark executor
 pub struct BenchmarkExecutor {
     config: MeasurementConfig,
+    /// User-supplied meter, taken out while a measurement is running
+    meter: Option<MeterSlot>,
 }
 
 impl BenchmarkExecutor {
     /// Create a new benchmark executor
     pub fn new(config: MeasurementConfig) -> Self {
-        Self { config }
+        Self {
+            config,
+            meter: None,
+        }
+    }
+
+    /// Create a benchmark executor that reads the given meter instead of
+    /// selecting one from `config.power_source`
+    pub fn with_meter(config: MeasurementConfig, meter: Box<dyn PowerMeter>) -> Self {
+        Self {
+            config,
+            meter: Some(Arc::new(Mutex::new(Some(meter)))),
+        }
     }
 
     /// Measure energy consumption of a given workload
     pub fn measure<F>(&self, workload: F) -> Result<EnergyMeasurement, MeasurementError>
     where
-        F: FnOnce() + Send + 'static,
+        F: FnOnce(),
     {
-        match self.config.power_source {
-            PowerSource::Auto => {
-                // Try RAPL first
-                if RaplMeasurement::new().is_ok() {
-                    return self.measure_with_rapl(workload);
-                }
-
-                // Try ACPI next
-                if AcpiMeasurement::new().is_ok() {
-                    return self.measure_with_acpi(workload);
-                }
-
-                // Fall back to TDP estimate
-                self.measure_with_tdp(workload)
-            }
-            PowerSource::Rapl => self.measure_with_rapl(workload),
-            PowerSource::Acpi => self.measure_with_acpi(workload),
-            PowerSource::TdpEstimate => self.measure_with_tdp(workload),
-        }
+        self.measure_with_output(workload)
+            .map(|((), measurement)| measurement)
     }
 
-    fn measure_with_rapl<F>(&self, workload: F) -> Result<EnergyMeasurement, MeasurementError>
+    /// Measure energy consumption of a given workload, returning what it
+    /// computed along with the measurement
+    ///
+    /// A panic in the workload ends the measurement and is returned as
+    /// [`MeasurementError::WorkloadPanicked`].
+    pub fn measure_with_output<F, T>(
+        &self,
+        workload: F,
+    ) -> Result<(T, EnergyMeasurement), MeasurementError>
     where
-        F: FnOnce() + Send + 'static,
+        F: FnOnce() -> T,
     {
-        let rapl = RaplMeasurement::new()?;
+        let handle = self.start()?;
+        // Dropping the handle stops sampling and hands the meter back
+        let output = panic::catch_unwind(AssertUnwindSafe(workload))
+            .map_err(|payload| MeasurementError::WorkloadPanicked(panic_message(&*payload)))?;
+        Ok((output, handle.stop()?))
+    }
 
-        // Initial reading
-        let start_energy = rapl.read_energy_counter()?;
-        let start_time = Instant::now();
+    /// Starts measuring the code that runs until [`MeterHandle::stop`]
+    ///
+    /// Sampling happens on a background thread, so the measured region may
+    /// borrow local state and span several calls. With a user-supplied
+    /// meter, only one handle can be active at a time.
+    pub fn start(&self) -> Result<MeterHandle<'_>, MeasurementError> {
+        let sample_interval = self.sample_interval();
+        let mut meter = self.take_meter()?;
+
+        // Idle phase before the workload
+        let before = match self.config.baseline {
+            Some(period) => {
+                let (power, idle_meter) =
+                    self.recover(measure_idle(meter, sample_interval, period))?;
+                meter = idle_meter;
+                Some((period, power))
+            }
+            None => None,
+        };
 
-        // Execute workload
-        workload();
+        // Spawn sampling thread
+        let markers = PhaseMarkers::default();
+        let regions = Regions::default();
+        let sampling_thread = self
+            .open_sampler(meter)?
+            .with_markers(markers.clone())
+            .with_regions(regions.clone())
+            .spawn(
+                sample_interval,
+                self.config.min_duration,
+                self.config.max_duration,
+            );
+
+        Ok(MeterHandle {
+            executor: self,
+            sampler: Some(sampling_thread),
+            before,
+            markers,
+            regions,
+        })
+    }
 
-        // Final reading
-        let end_energy = rapl.read_energy_counter()?;
-        let duration = start_time.elapsed();
+    /// Measure energy consumption of a future, sampling on a tokio interval
+    /// task instead of a thread, and return its output with the measurement
+    ///
+    /// Must be called within a tokio runtime. A panic in the future is
+    /// returned as [`MeasurementError::WorkloadPanicked`]. Readings are taken
+    /// on the blocking thread pool, except for the initial one, which opens
+    /// the meter on the calling task. If the future is dropped before it
+    /// completes, a user-supplied meter returns to the executor shortly
+    /// after.
+    pub async fn measure_async<Fut, T>(
+        &self,
+        workload: Fut,
+    ) -> Result<(T, EnergyMeasurement), MeasurementError>
+    where
+        Fut: Future<Output = T>,
+    {
+        self.measure_async_with_markers(|_| workload).await
+    }
 
-        // Convert microjoules to joules
-        let energy_joules = (end_energy - start_energy) as f64 / 1_000_000.0;
-        let average_power_watts = energy_joules / duration.as_secs_f64();
+    /// Like [`BenchmarkExecutor::measure_async`], handing the workload the
+    /// markers that split the measurement into phases
+    pub async fn measure_async_with_markers<F, Fut, T>(
+        &self,
+        workload: F,
+    ) -> Result<(T, EnergyMeasurement), MeasurementError>
+    where
+        F: FnOnce(PhaseMarkers) -> Fut,
+        Fut: Future<Output = T>,
+    {
+        self.measure_async_with_regions(|markers, _| workload(markers))
+            .await
+    }
 
-        let total_energy: Energy = Energy::new::<joule>(energy_joules);
+    /// Like [`BenchmarkExecutor::measure_async`], handing the workload the
+    /// markers that split the measurement into phases and the regions
+    /// reported in [`EnergyMeasurement::regions`]
+    pub async fn measure_async_with_regions<F, Fut, T>(
+        &self,
+        workload: F,
+    ) -> Result<(T, EnergyMeasurement), MeasurementError>
+    where
+        F: FnOnce(PhaseMarkers, Regions) -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let sample_interval = self.sample_interval();
+        let mut meter = self.take_meter()?;
+
+        // Idle phase before the workload
+        let before = match self.config.baseline {
+            Some(period) => {
+                let idle = measure_idle_async(meter, sample_interval, period, self.meter.clone());
+                let (power, idle_meter) = self.recover(idle.await)?;
+                meter = idle_meter;
+                Some((period, power))
+            }
+            None => None,
+        };
 
-        let samples = [0.0];
+        let markers = PhaseMarkers::default();
+        let regions = Regions::default();
+        let task = self
+            .open_sampler(meter)?
+            .with_markers(markers.clone())
+            .with_regions(regions.clone())
+            .spawn_task(
+                sample_interval,
+                self.config.min_duration,
+                self.config.max_duration,
+                self.meter.clone(),
+            );
+
+        let mut workload = pin!(workload(markers, regions));
+        let output = future::poll_fn(|cx| {
+            match panic::catch_unwind(AssertUnwindSafe(|| workload.as_mut().poll(cx))) {
+                Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+                Ok(Poll::Pending) => Poll::Pending,
+                Err(payload) => Poll::Ready(Err(payload)),
+            }
+        })
+        .await;
+        let output = match output {
+            Ok(output) => output,
+            Err(payload) => {
+                if let Ok(sampler) = self.recover(task.stop().await) {
+                    self.return_meter(sampler.finish().1);
+                }
+                return Err(MeasurementError::WorkloadPanicked(panic_message(&*payload)));
+            }
+        };
 
-        let peak_power = samples.iter().cloned().fold(0.0, f64::max);
+        let (mut measurement, mut meter) = self.recover(task.stop().await)?.finish();
+        let after = match (before, self.config.baseline_after) {
+            (Some((period, _)), true) => {
+                let idle = measure_idle_async(meter, sample_interval, period, self.meter.clone());
+                let (power, idle_meter) = self.recover(idle.await)?;
+                meter = idle_meter;
+                Some(power)
+            }
+            _ => None,
+        };
+        record_baseline(&mut measurement, before, after);
+        self.return_meter(meter);
+        Ok((output, measurement))
+    }
 
-        let peak_power = Power::new::<watt>(peak_power);
+    fn sample_interval(&self) -> Duration {
+        Duration::from_millis(self.config.sample_interval_ms)
+    }
 
-        let average_power = Power::new::<watt>(average_power_watts);
+    /// Opens the optional probes and takes the initial reading, handing
+    /// the meter back if a probe cannot be opened
+    fn open_sampler(&self, meter: Box<dyn PowerMeter>) -> Result<Sampler, MeasurementError> {
+        // Optional probes start right before the workload
+        let probes = (|| -> Result<_, MeasurementError> {
+            Ok((
+                WorkloadTracker::open(&self.config)?,
+                MemoryModel::open(&self.config)?,
+                NetworkCounter::open(&self.config)?,
+                StorageCounter::open(&self.config)?,
+            ))
+        })();
+        let (tracker, memory, network, storage) = match probes {
+            Ok(probes) => probes,
+            Err(e) => {
+                self.return_meter(meter);
+                return Err(e);
+            }
+        };
 
-        Ok(EnergyMeasurement {
-            duration,
-            measurement_method: PowerSource::Rapl,
-            total_energy,
-            average_power,
-            peak_power,
-        })
+        // Initial reading
+        Ok(self
+            .recover(Sampler::start(meter))?
+            .with_tracker(tracker)
+            .with_memory(memory)
+            .with_network(network)
+            .with_storage(storage))
     }
 
-    fn measure_with_acpi<F>(&self, workload: F) -> Result<EnergyMeasurement, MeasurementError>
+    /// Measure `workload` `runs` times after `warmup` discarded runs and
+    /// summarize the results
+    pub fn measure_repeated<F>(
+        &self,
+        runs: usize,
+        warmup: usize,
+        workload: F,
+    ) -> Result<RepeatedMeasurement, MeasurementError>
     where
-        F: FnOnce() + Send + 'static,
+        F: Fn(),
     {
-        let acpi = AcpiMeasurement::new()?;
-
-        // Initial reading
-        let start_time = Instant::now();
-        let mut samples = Vec::new();
-        let mut peak_power = 0.0;
+        if runs == 0 {
+            return Err(MeasurementError::InvalidMeasurement(
+                "at least one run is required".to_string(),
+            ));
+        }
+        let mut measurements = Vec::with_capacity(runs);
+        for run in 0..warmup + runs {
+            let measurement = self.measure(&workload)?;
+            if run >= warmup {
+                measurements.push(measurement);
+            }
+        }
+        RepeatedMeasurement::new(measurements, warmup)
+    }
 
-        // Spawn sampling thread
-        let sample_interval = Duration::from_millis(self.config.sample_interval_ms);
-        let duration = self.config.duration;
-        let sampling_thread = thread::spawn(move || {
-            let mut local_samples = Vec::new();
-            let mut local_peak: f64 = 0.0;
-
-            while start_time.elapsed() < duration {
-                if let Ok(info) = acpi.read_power_info() {
-                    let power = acpi.calculate_power(&info);
-                    local_samples.push(power);
-                    local_peak = local_peak.max(power);
+    /// Measures named workloads interleaved, one run of each per round, so
+    /// that drift in the system affects them alike, and compares their
+    /// energy after discarding the `warmup` rounds
+    pub fn compare<F>(
+        &self,
+        workloads: Vec<(String, F)>,
+        rounds: usize,
+        warmup: usize,
+    ) -> Result<Comparison, MeasurementError>
+    where
+        F: Fn(),
+    {
+        if workloads.len() < 2 || rounds == 0 {
+            return Err(MeasurementError::InvalidMeasurement(
+                "at least two workloads and one round are required".to_string(),
+            ));
+        }
+        let mut measurements: Vec<Vec<EnergyMeasurement>> = workloads
+            .iter()
+            .map(|_| Vec::with_capacity(rounds))
+            .collect();
+        for round in 0..warmup + rounds {
+            for ((_, workload), runs) in workloads.iter().zip(&mut measurements) {
+                let measurement = self.measure(workload)?;
+                if round >= warmup {
+                    runs.push(measurement);
                 }
-                thread::sleep(sample_interval);
             }
-
-            (local_samples, local_peak)
-        });
-
-        // Execute workload
-        workload();
-
-        // Collect measurements
-        if let Ok((local_samples, local_peak)) = sampling_thread.join() {
-            samples = local_samples;
-            peak_power = local_peak;
         }
+        let repeated = workloads
+            .into_iter()
+            .zip(measurements)
+            .map(|((name, _), runs)| Ok((name, RepeatedMeasurement::new(runs, warmup)?)))
+            .collect::<Result<_, MeasurementError>>()?;
+        Comparison::new(repeated)
+    }
 
-        let duration = start_time.elapsed();
-
-        // Calculate average power and total energy
-        let average_power = if !samples.is_empty() {
-            samples.iter().sum::<f64>() / samples.len() as f64
-        } else {
-            0.0
-        };
-
-        let total_energy = average_power * duration.as_secs_f64();
-        let total_energy = Energy::new::<joule>(total_energy);
+    fn take_meter(&self) -> Result<Box<dyn PowerMeter>, MeasurementError> {
+        match &self.meter {
+            Some(meter) => meter
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .take()
+                .ok_or_else(|| {
+                    MeasurementError::Unsupported(
+                        "the meter is already in use by another measurement".to_string(),
+                    )
+                }),
+            None => open_meter(self.config.power_source, &self.config),
+        }
+    }
 
-        let average_power = Power::new::<watt>(average_power);
-        let peak_power = Power::new::<watt>(peak_power);
+    fn return_meter(&self, meter: Box<dyn PowerMeter>) {
+        if let Some(slot) = &self.meter {
+            *slot.lock().unwrap_or_else(|e| e.into_inner()) = Some(meter);
+        }
+    }
 
-        Ok(EnergyMeasurement {
-            total_energy,
-            average_power,
-            peak_power,
-            duration,
-            measurement_method: PowerSource::Acpi,
+    /// Hands the meter of a failed reading back before returning the error,
+    /// so that later measurements can use it
+    fn recover<T>(&self, result: Result<T, MeterError>) -> Result<T, MeasurementError> {
+        result.map_err(|MeterError { error, meter }| {
+            if let Some(meter) = meter {
+                self.return_meter(meter);
+            }
+            error
         })
     }
+}
 
-    fn measure_with_tdp<F>(&self, workload: F) -> Result<EnergyMeasurement, MeasurementError>
-    where
-        F: FnOnce() + Send + 'static,
-    {
-        let start_time = Instant::now();
+/// Adds the baseline from the idle phases before and after the workload,
+/// if they were measured
+fn record_baseline(
+    measurement: &mut EnergyMeasurement,
+    before: Option<(Duration, Power)>,
+    after: Option<Power>,
+) {
+    if let Some((period, before_power)) = before {
+        measurement.baseline = Some(Baseline::new(
+            period,
+            before_power,
+            after,
+            measurement.duration,
+            measurement.total_energy,
+        ));
+    }
+}
 
-        // Execute workload
-        workload();
+/// The message a panic was raised with
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "non-string panic payload".to_string()
+    }
+}
 
-        let duration = start_time.elapsed();
+/// Opens the built-in meter for `source`
+fn open_meter(
+    source: PowerSource,
+    config: &MeasurementConfig,
+) -> Result<Box<dyn PowerMeter>, MeasurementError> {
+    let root = &config.fs_root;
+    match source {
+        PowerSource::Auto => {
+            // Try RAPL first
+            if let Ok(rapl) = RaplMeasurement::with_root(root) {
+                return Ok(Box::new(rapl));
+            }
 
-        // Estimate using a conservative TDP value (example: 28W for laptop CPU)
-        let estimated_tdp = 28.0; // This should be configurable
-        let energy_joules = estimated_tdp * duration.as_secs_f64();
+            // The same counters through perf, where energy_uj is root-only
+            if let Ok(perf) = PerfRaplMeasurement::with_root(root) {
+                return Ok(Box::new(perf));
+            }
 
-        let total_energy = Energy::new::<joule>(energy_joules);
-        let average_power = Power::new::<watt>(estimated_tdp);
-        let peak_power = Power::new::<watt>(estimated_tdp);
+            // Then hwmon sensors of the CPU, leaving out GPUs and chargers
+            if let Ok(hwmon) = HwmonMeasurement::cpu_with_root(root) {
+                return Ok(Box::new(hwmon));
+            }
 
-        Ok(EnergyMeasurement {
-            total_energy,
-            average_power,
-            peak_power,
-            duration,
-            measurement_method: PowerSource::TdpEstimate,
-        })
+            // Try ACPI next
+            if let Ok(acpi) = AcpiMeasurement::with_root(root) {
+                return Ok(Box::new(acpi));
+            }
+
+            // Fall back to TDP estimate
+            open_meter(PowerSource::TdpEstimate, config)
+        }
+        PowerSource::Rapl => Ok(Box::new(RaplMeasurement::with_root(root)?)),
+        PowerSource::PerfRapl => Ok(Box::new(PerfRaplMeasurement::with_root(root)?)),
+        PowerSource::Acpi => Ok(Box::new(AcpiMeasurement::with_root(root)?)),
+        PowerSource::AcpiEnergy => Ok(Box::new(AcpiEnergyMeasurement::with_root(root)?)),
+        PowerSource::Hwmon => Ok(Box::new(HwmonMeasurement::with_root(root)?)),
+        PowerSource::TdpEstimate => {
+            let profile = CpuProfile::from_config(config);
+            let (idle, max) = (profile.idle_watts, profile.max_watts);
+            let model = CpuPowerModel::with_root(idle, max, config.utilization, root)
+                .and_then(|model| model.following(config));
+            match model {
+                Ok(model) => Ok(Box::new(model.with_profile(profile))),
+                // Without /proc/stat all that is left is assuming full load
+                Err(_) => Ok(Box::new(TdpEstimator::new(max).with_profile(profile))),
+            }
+        }
+        PowerSource::Custom => Err(MeasurementError::Unsupported(
+            "a custom power source needs BenchmarkExecutor::with_meter".to_string(),
+        )),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testutil::FakeRoot;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
 
     #[test]
     fn test_gigabytes_to_kwh() {
@@ -584,9 +1841,9 @@ mod tests {
     #[test]
     fn test_tdp_measurement() {
         let config = MeasurementConfig {
-            duration: Duration::from_secs(1),
             power_source: PowerSource::TdpEstimate,
             sample_interval_ms: 100,
+            ..Default::default()
         };
 
         let executor = BenchmarkExecutor::new(config);
@@ -604,6 +1861,876 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_rapl_counter_delta() {
+        assert_eq!(counter_delta(100, 250, Some(1_000)), 150);
+        // Wrapped past max_energy_range_uj
+        assert_eq!(counter_delta(900, 50, Some(1_000)), 150);
+        // Unknown range falls back to counting from zero
+        assert_eq!(counter_delta(900, 50, None), 50);
+    }
+
+    /// A meter that counts energy at a constant 10 W
+    struct ConstantMeter {
+        created: Instant,
+    }
+
+    impl PowerMeter for ConstantMeter {
+        fn capabilities(&self) -> MeterCapabilities {
+            MeterCapabilities {
+                instantaneous_power: false,
+                cumulative_energy: true,
+                power_resolution: None,
+                energy_resolution: None,
+            }
+        }
+
+        fn read_energy(&mut self) -> Result<Energy, MeasurementError> {
+            Ok(Energy::new::<joule>(
+                10.0 * self.created.elapsed().as_secs_f64(),
+            ))
+        }
+    }
+
+    /// An executor sampling a [`ConstantMeter`] every 10 ms
+    fn constant_executor(config: MeasurementConfig) -> BenchmarkExecutor {
+        let config = MeasurementConfig {
+            sample_interval_ms: 10,
+            ..config
+        };
+        let meter = ConstantMeter {
+            created: Instant::now(),
+        };
+        BenchmarkExecutor::with_meter(config, Box::new(meter))
+    }
+
+    /// A meter that counts energy at 10 W, failing while `failing` is set
+    struct FlakyMeter {
+        created: Instant,
+        failing: Arc<AtomicBool>,
+    }
+
+    impl PowerMeter for FlakyMeter {
+        fn capabilities(&self) -> MeterCapabilities {
+            MeterCapabilities {
+                instantaneous_power: false,
+                cumulative_energy: true,
+                power_resolution: None,
+                energy_resolution: None,
+            }
+        }
+
+        fn read_energy(&mut self) -> Result<Energy, MeasurementError> {
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(MeasurementError::IoError(io::Error::other("read failed")));
+            }
+            Ok(Energy::new::<joule>(
+                10.0 * self.created.elapsed().as_secs_f64(),
+            ))
+        }
+    }
+
+    /// A meter drawing 10 W, or 30 W while `busy` is set
+    struct BusyMeter {
+        busy: Arc<AtomicBool>,
+    }
+
+    impl PowerMeter for BusyMeter {
+        fn capabilities(&self) -> MeterCapabilities {
+            MeterCapabilities {
+                instantaneous_power: true,
+                cumulative_energy: false,
+                power_resolution: None,
+                energy_resolution: None,
+            }
+        }
+
+        fn read_power(&mut self) -> Result<Power, MeasurementError> {
+            let busy = self.busy.load(Ordering::SeqCst);
+            Ok(Power::new::<watt>(if busy { 30.0 } else { 10.0 }))
+        }
+    }
+
+    /// A meter whose power rises by 100 W every second
+    struct RampMeter {
+        created: Instant,
+    }
+
+    impl PowerMeter for RampMeter {
+        fn capabilities(&self) -> MeterCapabilities {
+            MeterCapabilities {
+                instantaneous_power: true,
+                cumulative_energy: false,
+                power_resolution: None,
+                energy_resolution: None,
+            }
+        }
+
+        fn read_power(&mut self) -> Result<Power, MeasurementError> {
+            Ok(Power::new::<watt>(
+                100.0 * self.created.elapsed().as_secs_f64(),
+            ))
+        }
+    }
+
+    #[test]
+    fn test_workload_lifetime() {
+        let config = MeasurementConfig {
+            sample_interval_ms: 10,
+            ..Default::default()
+        };
+        let created = Instant::now();
+        let executor = BenchmarkExecutor::with_meter(config, Box::new(RampMeter { created }));
+
+        // Sampling follows the workload rather than a fixed window
+        let start = Instant::now();
+        let measurement = executor
+            .measure(|| thread::sleep(Duration::from_millis(200)))
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_millis(500));
+        let seconds = measurement.duration.as_secs_f64();
+        assert!((0.2..0.3).contains(&seconds), "duration {seconds}");
+        // The trapezoidal rule is exact for a linear ramp
+        let first = &measurement.samples[0];
+        let last = measurement.samples.last().unwrap();
+        let expected = (first.power + last.power).get::<watt>() / 2.0 * seconds;
+        let joules = measurement.total_energy.get::<joule>();
+        assert!((joules - expected).abs() < 0.01 * expected, "{joules} J");
+
+        let config = MeasurementConfig {
+            sample_interval_ms: 10,
+            min_duration: Some(Duration::from_millis(100)),
+            max_duration: Some(Duration::from_millis(150)),
+            ..Default::default()
+        };
+        let executor = BenchmarkExecutor::with_meter(config, Box::new(RampMeter { created }));
+        let measurement = executor.measure(|| {}).unwrap();
+        assert!(measurement.duration >= Duration::from_millis(100));
+        let measurement = executor
+            .measure(|| thread::sleep(Duration::from_millis(300)))
+            .unwrap();
+        assert!(measurement.duration < Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_baseline_phases() {
+        let executor = constant_executor(MeasurementConfig {
+            baseline: Some(Duration::from_millis(50)),
+            baseline_after: true,
+            ..Default::default()
+        });
+        let measurement = executor
+            .measure(|| thread::sleep(Duration::from_millis(50)))
+            .unwrap();
+        let baseline = measurement.baseline.as_ref().unwrap();
+        assert_eq!(baseline.method, BaselineMethod::BeforeAndAfter);
+        assert!((baseline.power.get::<watt>() - 10.0).abs() < 0.5);
+        // A constant draw is all idle
+        assert!(measurement.net_energy().get::<joule>() < 0.05);
+        assert!(measurement.total_energy.get::<joule>() > 0.4);
+    }
+
+    #[test]
+    fn test_measure_repeated() {
+        let executor = constant_executor(MeasurementConfig::default());
+
+        let runs = Arc::new(Mutex::new(0));
+        let repeated = executor
+            .measure_repeated(3, 2, {
+                let runs = runs.clone();
+                move || {
+                    *runs.lock().unwrap() += 1;
+                    thread::sleep(Duration::from_millis(20));
+                }
+            })
+            .unwrap();
+        assert_eq!(*runs.lock().unwrap(), 5);
+        assert_eq!(repeated.runs.len(), 3);
+        assert_eq!(repeated.warmup, 2);
+        assert!((repeated.average_power.mean - 10.0).abs() < 0.5);
+        assert!(repeated.energy.ci_low <= repeated.energy.mean);
+        assert_eq!(repeated.net_energy, None);
+        assert_eq!(repeated.workload_energy(), &repeated.energy);
+
+        assert!(executor.measure_repeated(0, 0, || ()).is_err());
+
+        // With a baseline the runs are judged by their net energy
+        let executor = constant_executor(MeasurementConfig {
+            baseline: Some(Duration::from_millis(20)),
+            ..Default::default()
+        });
+        let repeated = executor
+            .measure_repeated(2, 0, || thread::sleep(Duration::from_millis(20)))
+            .unwrap();
+        let net_energy = repeated.net_energy.unwrap();
+        assert_eq!(repeated.workload_energy(), &net_energy);
+        // A constant draw is all idle
+        assert!(net_energy.mean < 0.05);
+        assert!((repeated.baseline_power.unwrap().mean - 10.0).abs() < 0.5);
+        assert_eq!(repeated.memory_energy, None);
+    }
+
+    #[test]
+    fn test_compare() {
+        let executor = constant_executor(MeasurementConfig::default());
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let workload = |name: &'static str, millis: u64| {
+            let order = order.clone();
+            let run = move || {
+                order.lock().unwrap().push(name);
+                thread::sleep(Duration::from_millis(millis));
+            };
+            (name.to_string(), run)
+        };
+        let comparison = executor
+            .compare(vec![workload("slow", 60), workload("fast", 20)], 3, 1)
+            .unwrap();
+        assert_eq!(
+            *order.lock().unwrap(),
+            ["slow", "fast", "slow", "fast", "slow", "fast", "slow", "fast"]
+        );
+        assert_eq!(comparison.reference, 1);
+        let slow = &comparison.workloads[0];
+        assert_eq!(slow.name, "slow");
+        assert_eq!(slow.measurement.runs.len(), 3);
+        assert!(slow.relative_energy > 2.0);
+        assert_eq!(comparison.workloads[1].relative_energy, 1.0);
+        assert!(comparison.workloads[1].test.is_none());
+
+        assert!(executor.compare(vec![workload("alone", 0)], 3, 0).is_err());
+    }
+
+    #[test]
+    fn test_compare_net_energy() {
+        let config = MeasurementConfig {
+            sample_interval_ms: 10,
+            baseline: Some(Duration::from_millis(30)),
+            ..Default::default()
+        };
+        let busy = Arc::new(AtomicBool::new(false));
+        let meter = BusyMeter { busy: busy.clone() };
+        let executor = BenchmarkExecutor::with_meter(config, Box::new(meter));
+
+        let workload = |name: &str, idle: u64, working: u64| {
+            let busy = busy.clone();
+            let run = move || {
+                thread::sleep(Duration::from_millis(idle));
+                busy.store(true, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(working));
+                busy.store(false, Ordering::SeqCst);
+            };
+            (name.to_string(), run)
+        };
+        // Waiting takes more energy in total, but little above idle
+        let comparison = executor
+            .compare(
+                vec![workload("wait", 150, 10), workload("work", 0, 40)],
+                2,
+                0,
+            )
+            .unwrap();
+        assert!(comparison.net_energy);
+        assert_eq!(comparison.reference, 0);
+        let (wait, work) = (&comparison.workloads[0], &comparison.workloads[1]);
+        assert!(wait.measurement.energy.mean > work.measurement.energy.mean);
+        assert!(work.relative_energy > 1.5, "{}", work.relative_energy);
+    }
+
+    #[test]
+    fn test_scoped_measurement() {
+        let executor = constant_executor(MeasurementConfig::default());
+
+        // The measured region borrows local state
+        let mut values = Vec::new();
+        let handle = executor.start().unwrap();
+        for i in 0..5 {
+            values.push(i);
+            thread::sleep(Duration::from_millis(10));
+        }
+        let measurement = handle.stop().unwrap();
+        assert_eq!(values.len(), 5);
+        assert!(measurement.duration >= Duration::from_millis(50));
+        assert!((measurement.average_power.get::<watt>() - 10.0).abs() < 0.5);
+
+        // Only one measurement can hold the meter, which dropping the
+        // handle returns
+        let handle = executor.start().unwrap();
+        assert!(matches!(
+            executor.start(),
+            Err(MeasurementError::Unsupported(_))
+        ));
+        drop(handle);
+        let measurement = executor
+            .measure(|| assert_eq!(values.iter().sum::<i32>(), 10))
+            .unwrap();
+        assert_eq!(measurement.measurement_method, PowerSource::Custom);
+    }
+
+    #[test]
+    fn test_phase_markers() {
+        let executor = constant_executor(MeasurementConfig::default());
+
+        let handle = executor.start().unwrap();
+        thread::sleep(Duration::from_millis(20));
+        handle.mark("fetch");
+        thread::sleep(Duration::from_millis(50));
+        let markers = handle.markers();
+        thread::spawn(move || markers.mark("compile"))
+            .join()
+            .unwrap();
+        thread::sleep(Duration::from_millis(50));
+        let measurement = handle.stop().unwrap();
+
+        let names: Vec<&str> = measurement.phases.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["fetch", "compile"]);
+        let fetch = &measurement.phases[0];
+        let compile = &measurement.phases[1];
+        assert!(fetch.start >= Duration::from_millis(20));
+        assert_eq!(fetch.start + fetch.duration, compile.start);
+        assert_eq!(compile.start + compile.duration, measurement.duration);
+        for phase in &measurement.phases {
+            assert!(phase.duration >= Duration::from_millis(50));
+            assert!((phase.average_power.get::<watt>() - 10.0).abs() < 0.5);
+        }
+        // The time before the first marker is not part of a phase
+        let phased = fetch.energy + compile.energy;
+        assert!(phased < measurement.total_energy);
+
+        let measurement = executor.measure(|| {}).unwrap();
+        assert!(measurement.phases.is_empty());
+    }
+
+    #[test]
+    fn test_nested_regions() {
+        let executor = constant_executor(MeasurementConfig::default());
+
+        let handle = executor.start().unwrap();
+        {
+            let service = handle.region("service");
+            for _ in 0..2 {
+                let _db = service.region("db");
+                thread::sleep(Duration::from_millis(30));
+            }
+            let regions = handle.regions();
+            thread::spawn(move || {
+                let _cache = regions.region("cache");
+                thread::sleep(Duration::from_millis(20));
+            })
+            .join()
+            .unwrap();
+        }
+        let measurement = handle.stop().unwrap();
+
+        let names: Vec<&str> = measurement
+            .regions
+            .iter()
+            .map(|r| r.name.as_str())
+            .collect();
+        assert_eq!(names, ["service", "cache"]);
+        let service = &measurement.regions[0];
+        let db = &service.children[0];
+        assert_eq!((db.name.as_str(), db.calls), ("db", 2));
+        assert!(db.duration >= Duration::from_millis(60));
+        assert!((db.inclusive_energy.get::<joule>() - 0.6).abs() < 0.1);
+        assert!(service.inclusive_energy >= db.inclusive_energy);
+        // The cache region ran inside service, but was entered at the top
+        // level
+        assert!(service.exclusive_energy.get::<joule>() > 0.1);
+        assert!(service.inclusive_energy <= measurement.total_energy);
+
+        let report = measurement.region_report().to_string();
+        assert_eq!(report.lines().count(), 4);
+        assert!(report.lines().nth(2).unwrap().starts_with("  db"));
+    }
+
+    #[test]
+    fn test_meter_survives_errors() {
+        let config = MeasurementConfig {
+            sample_interval_ms: 10,
+            baseline: Some(Duration::from_millis(20)),
+            baseline_after: true,
+            ..Default::default()
+        };
+        let failing = Arc::new(AtomicBool::new(true));
+        let meter = FlakyMeter {
+            created: Instant::now(),
+            failing: failing.clone(),
+        };
+        let executor = BenchmarkExecutor::with_meter(config, Box::new(meter));
+
+        // The idle phase before the workload fails
+        assert!(matches!(
+            executor.measure(|| {}),
+            Err(MeasurementError::IoError(_))
+        ));
+        // The final reading fails
+        failing.store(false, Ordering::SeqCst);
+        let result = executor.start().and_then(|handle| {
+            failing.store(true, Ordering::SeqCst);
+            handle.stop()
+        });
+        assert!(matches!(result, Err(MeasurementError::IoError(_))));
+
+        // None of the errors loses the meter
+        failing.store(false, Ordering::SeqCst);
+        let measurement = executor.measure(|| {}).unwrap();
+        assert!(measurement.baseline.is_some());
+    }
+
+    #[test]
+    fn test_workload_output_and_panic() {
+        let executor = constant_executor(MeasurementConfig::default());
+
+        let words = ["energy", "meter"];
+        let (length, measurement) = executor
+            .measure_with_output(|| words.iter().map(|word| word.len()).sum::<usize>())
+            .unwrap();
+        assert_eq!(length, 11);
+        assert_eq!(measurement.measurement_method, PowerSource::Custom);
+
+        let result = executor.measure(|| panic!("workload failed after {} steps", 3));
+        assert!(matches!(
+            result,
+            Err(MeasurementError::WorkloadPanicked(message))
+                if message == "workload failed after 3 steps"
+        ));
+        // The meter survives the panic
+        assert!(executor.measure(|| {}).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_measure_async() {
+        let executor = constant_executor(MeasurementConfig {
+            baseline: Some(Duration::from_millis(30)),
+            baseline_after: true,
+            ..Default::default()
+        });
+
+        let (answer, measurement) = executor
+            .measure_async(async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                42
+            })
+            .await
+            .unwrap();
+        assert_eq!(answer, 42);
+        assert!(measurement.duration >= Duration::from_millis(50));
+        assert!((measurement.average_power.get::<watt>() - 10.0).abs() < 0.5);
+        // Several readings from the interval task
+        assert!(measurement.samples.len() > 2);
+        let baseline = measurement.baseline.unwrap();
+        assert_eq!(baseline.method, BaselineMethod::BeforeAndAfter);
+
+        let ((), measurement) = executor
+            .measure_async_with_regions(|_, regions| async move {
+                let _query = regions.region("query");
+                tokio::time::sleep(Duration::from_millis(30)).await;
+            })
+            .await
+            .unwrap();
+        assert_eq!(measurement.regions[0].name, "query");
+        assert!(measurement.regions[0].duration >= Duration::from_millis(30));
+
+        let result = executor
+            .measure_async(async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                panic!("async workload failed");
+            })
+            .await;
+        assert!(matches!(result, Err(MeasurementError::WorkloadPanicked(_))));
+        assert!(executor.measure_async(async {}).await.is_ok());
+
+        // Cancelling the measurement, in the workload or the idle phases,
+        // does not lose the meter
+        for cancel_after in [10, 80, 145] {
+            let measurement = executor.measure_async(async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            });
+            let cancelled = tokio::time::timeout(Duration::from_millis(cancel_after), measurement);
+            assert!(cancelled.await.is_err());
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert!(executor.measure_async(async {}).await.is_ok());
+        }
+    }
+
+    #[test]
+    fn test_custom_meter() {
+        let executor = constant_executor(MeasurementConfig {
+            power_source: PowerSource::Auto,
+            ..Default::default()
+        });
+        // The meter is handed back after each measurement
+        for _ in 0..2 {
+            let measurement = executor
+                .measure(|| thread::sleep(Duration::from_millis(50)))
+                .unwrap();
+            assert_eq!(measurement.measurement_method, PowerSource::Custom);
+            let watts = measurement.average_power.get::<watt>();
+            assert!((watts - 10.0).abs() < 0.5, "average power {watts}");
+            assert!(measurement.peak_power.get::<watt>() > 0.0);
+        }
+    }
+
+    /// A laptop reporting power_now on its battery next to an AC adapter
+    fn laptop_power_now() -> FakeRoot {
+        let root = FakeRoot::new("laptop");
+        root.file("sys/class/power_supply/AC/online", "0\n")
+            .file("sys/class/power_supply/BAT0/status", "Discharging\n")
+            .file("sys/class/power_supply/BAT0/voltage_now", "12100000\n")
+            .file("sys/class/power_supply/BAT0/power_now", "15250000\n")
+            .file("sys/class/power_supply/BAT0/energy_now", "41230000\n");
+        root
+    }
+
+    #[test]
+    fn test_acpi_read_power_info() {
+        let root = laptop_power_now();
+        let acpi = AcpiMeasurement::with_root(root.path()).unwrap();
+        // The adapter exposes nothing but `online`
+        assert_eq!(
+            acpi.power_supplies().collect::<Vec<_>>(),
+            [("BAT0", PowerSupplyKind::Battery)]
+        );
+
+        let info = acpi.read_power_info().unwrap();
+        assert_eq!(info.len(), 1);
+        assert_eq!(info[0].power_now, Some(15_250_000.0));
+        assert_eq!(info[0].voltage_now, 12_100_000.0);
+        assert_eq!(info[0].current_now, 0.0);
+
+        assert!((acpi.calculate_power(&info) - 15.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_acpi_energy_counter() {
+        let root = laptop_power_now();
+        root.file("sys/class/power_supply/BAT1/voltage_now", "12000000\n")
+            .file("sys/class/power_supply/BAT1/charge_now", "3000000\n");
+        let mut acpi = AcpiEnergyMeasurement::with_root(root.path()).unwrap();
+        let quantities: Vec<_> = acpi.batteries.iter().map(|b| b.quantity).collect();
+        assert_eq!(
+            quantities,
+            [BatteryQuantity::EnergyNow, BatteryQuantity::ChargeNow]
+        );
+        assert_eq!(acpi.read_energy().unwrap().get::<joule>(), 0.0);
+
+        // 1000 μWh, and 1000 μAh at 11.5 V on average
+        root.file("sys/class/power_supply/BAT0/energy_now", "41229000\n")
+            .file("sys/class/power_supply/BAT1/charge_now", "2999000\n")
+            .file("sys/class/power_supply/BAT1/voltage_now", "11000000\n");
+        assert!((acpi.read_energy().unwrap().get::<joule>() - 45.0).abs() < 1e-9);
+        // Charging does not count
+        root.file("sys/class/power_supply/BAT0/energy_now", "41240000\n");
+        assert!((acpi.read_energy().unwrap().get::<joule>() - 45.0).abs() < 1e-9);
+        let domains = PowerMeter::domains(&acpi);
+        assert!((domains[0].energy.get::<joule>() - 3.6).abs() < 1e-9);
+
+        // An adapter alone has no counter
+        let root = FakeRoot::new("adapter-only");
+        root.file("sys/class/power_supply/AC/online", "1\n");
+        assert!(matches!(
+            AcpiEnergyMeasurement::with_root(root.path()),
+            Err(MeasurementError::AcpiNotAvailable)
+        ));
+    }
+
+    #[test]
+    fn test_acpi_energy_updates() {
+        let root = laptop_power_now();
+        let config = MeasurementConfig {
+            power_source: PowerSource::AcpiEnergy,
+            fs_root: root.path().to_path_buf(),
+            sample_interval_ms: 10,
+            ..Default::default()
+        };
+        let executor = BenchmarkExecutor::new(config);
+        let energy_now = root.path().join("sys/class/power_supply/BAT0/energy_now");
+        let measurement = executor
+            .measure(move || {
+                thread::sleep(Duration::from_millis(30));
+                fs::write(&energy_now, "41229000\n").unwrap();
+                thread::sleep(Duration::from_millis(30));
+            })
+            .unwrap();
+        assert_eq!(measurement.measurement_method, PowerSource::AcpiEnergy);
+        assert!((measurement.total_energy.get::<joule>() - 3.6).abs() < 1e-9);
+        assert_eq!(measurement.batteries.len(), 1);
+        assert_eq!(measurement.batteries[0].updates, 1);
+        assert_eq!(measurement.batteries[0].update_interval, None);
+        assert_eq!(measurement.warnings.len(), 1);
+        assert!(measurement.warnings[0].contains("changed only once"));
+
+        // A counter that never moves is flagged as such
+        let measurement = executor.measure(|| {}).unwrap();
+        assert_eq!(measurement.total_energy.get::<joule>(), 0.0);
+        assert!(measurement.warnings[0].contains("did not change"));
+    }
+
+    #[test]
+    fn test_acpi_voltage_and_current() {
+        // Batteries without power_now report voltage and current instead
+        let root = FakeRoot::new("laptop-current");
+        root.file("sys/class/power_supply/BAT0/voltage_now", "11400000\n")
+            .file("sys/class/power_supply/BAT0/current_now", "1500000\n")
+            .file("sys/class/power_supply/BAT1/voltage_now", "11400000\n")
+            .file("sys/class/power_supply/BAT1/current_now", "500000\n");
+
+        let mut acpi = AcpiMeasurement::with_root(root.path()).unwrap();
+        let info = acpi.read_power_info().unwrap();
+        assert!((acpi.calculate_power(&info) - 22.8).abs() < 1e-9);
+        let power = acpi.read_power().unwrap();
+        assert!((power.get::<watt>() - 22.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_acpi_supply_discovery() {
+        let root = FakeRoot::new("laptop-typed");
+        let supply = |name: &str, kind: &str| format!("sys/class/power_supply/{name}/{kind}");
+        root.file(&supply("ADP1", "type"), "Mains\n")
+            .file(&supply("ADP1", "online"), "1\n")
+            .file(&supply("macsmc-battery", "type"), "Battery\n")
+            .file(&supply("macsmc-battery", "scope"), "System\n")
+            .file(&supply("macsmc-battery", "status"), "Charging\n")
+            .file(&supply("macsmc-battery", "power_now"), "10000000\n")
+            .file(&supply("hid-mouse-battery", "type"), "Battery\n")
+            .file(&supply("hid-mouse-battery", "scope"), "Device\n")
+            .file(&supply("hid-mouse-battery", "power_now"), "50000\n")
+            .file(&supply("ucsi-source-psy-USBC000:001", "type"), "USB\n")
+            .file(
+                &supply("ucsi-source-psy-USBC000:001", "voltage_now"),
+                "20000000\n",
+            )
+            .file(
+                &supply("ucsi-source-psy-USBC000:001", "current_now"),
+                "3000000\n",
+            );
+
+        let mut acpi = AcpiMeasurement::with_root(root.path()).unwrap();
+        assert_eq!(
+            acpi.power_supplies().collect::<Vec<_>>(),
+            [
+                ("macsmc-battery", PowerSupplyKind::Battery),
+                ("ucsi-source-psy-USBC000:001", PowerSupplyKind::Usb)
+            ]
+        );
+        // The USB-C source powers the system and charges the battery
+        assert!((acpi.read_power().unwrap().get::<watt>() - 60.0).abs() < 1e-9);
+
+        let config = MeasurementConfig {
+            power_source: PowerSource::Acpi,
+            fs_root: root.path().to_path_buf(),
+            sample_interval_ms: 10,
+            ..Default::default()
+        };
+        let measurement = BenchmarkExecutor::new(config).measure(|| {}).unwrap();
+        assert_eq!(measurement.power_supplies.len(), 2);
+        assert_eq!(
+            measurement.power_supplies[0].status.as_deref(),
+            Some("Charging")
+        );
+        assert!(measurement.warnings.is_empty());
+
+        // Without the source, the battery's charge rate is all there is
+        fs::remove_dir_all(
+            root.path()
+                .join("sys/class/power_supply/ucsi-source-psy-USBC000:001"),
+        )
+        .unwrap();
+        let config = MeasurementConfig {
+            power_source: PowerSource::Acpi,
+            fs_root: root.path().to_path_buf(),
+            sample_interval_ms: 10,
+            ..Default::default()
+        };
+        let measurement = BenchmarkExecutor::new(config).measure(|| {}).unwrap();
+        assert_eq!(measurement.warnings.len(), 1);
+        assert!(measurement.warnings[0].starts_with("macsmc-battery is charging"));
+    }
+
+    #[test]
+    fn test_acpi_missing_supplies() {
+        let root = FakeRoot::new("no-power-supply");
+        assert!(matches!(
+            AcpiMeasurement::with_root(root.path()),
+            Err(MeasurementError::AcpiNotAvailable)
+        ));
+
+        // A desktop with only a UPS exposes no BAT or AC supplies
+        root.file("sys/class/power_supply/ups/status", "Online\n");
+        assert!(matches!(
+            AcpiMeasurement::with_root(root.path()),
+            Err(MeasurementError::AcpiNotAvailable)
+        ));
+    }
+
+    #[test]
+    fn test_acpi_unparsable_value() {
+        let root = FakeRoot::new("laptop-garbage");
+        root.file("sys/class/power_supply/BAT0/power_now", "N/A\n");
+
+        let acpi = AcpiMeasurement::with_root(root.path()).unwrap();
+        assert!(matches!(
+            acpi.read_power_info(),
+            Err(MeasurementError::InvalidMeasurement(_))
+        ));
+    }
+
+    /// A dual-socket server with core and dram subzones on each package
+    fn dual_socket_server() -> FakeRoot {
+        let root = FakeRoot::new("server");
+        for package in 0..2 {
+            let base = format!("sys/class/powercap/intel-rapl/intel-rapl:{package}");
+            root.file(&format!("{base}/name"), &format!("package-{package}\n"))
+                .file(&format!("{base}/energy_uj"), "1000000\n")
+                .file(&format!("{base}/max_energy_range_uj"), "262143328850\n");
+            for (zone, name) in ["core", "dram"].iter().enumerate() {
+                let base = format!("{base}/intel-rapl:{package}:{zone}");
+                root.file(&format!("{base}/name"), &format!("{name}\n"))
+                    .file(&format!("{base}/energy_uj"), "500000\n")
+                    .file(&format!("{base}/max_energy_range_uj"), "262143328850\n");
+            }
+        }
+        root
+    }
+
+    #[test]
+    fn test_rapl_multi_socket_domains() {
+        let root = dual_socket_server();
+        let mut rapl = RaplMeasurement::with_root(root.path()).unwrap();
+        assert_eq!(
+            rapl.domains().collect::<Vec<_>>(),
+            [
+                "package-0",
+                "package-0/core",
+                "package-0/dram",
+                "package-1",
+                "package-1/core",
+                "package-1/dram"
+            ]
+        );
+
+        let base = "sys/class/powercap/intel-rapl";
+        root.file(&format!("{base}/intel-rapl:0/energy_uj"), "3000000\n")
+            .file(
+                &format!("{base}/intel-rapl:0/intel-rapl:0:0/energy_uj"),
+                "1500000\n",
+            )
+            .file(
+                &format!("{base}/intel-rapl:0/intel-rapl:0:1/energy_uj"),
+                "750000\n",
+            )
+            .file(&format!("{base}/intel-rapl:1/energy_uj"), "2000000\n");
+
+        // Packages and dram count, core is already part of its package
+        let energy = rapl.read_energy().unwrap();
+        assert!((energy.get::<joule>() - 3.25).abs() < 1e-9);
+
+        let domains = PowerMeter::domains(&rapl);
+        assert_eq!(domains[0].energy.get::<joule>(), 2.0);
+        assert!(domains[0].included_in_total);
+        assert_eq!(domains[1].energy.get::<joule>(), 1.0);
+        assert!(!domains[1].included_in_total);
+        assert_eq!(domains[2].energy.get::<joule>(), 0.25);
+        assert!(domains[2].included_in_total);
+        assert_eq!(domains[3].energy.get::<joule>(), 1.0);
+    }
+
+    #[test]
+    fn test_rapl_wrapped_counter() {
+        let root = FakeRoot::new("rapl-wrap");
+        let base = "sys/class/powercap/intel-rapl/intel-rapl:0";
+        root.file(&format!("{base}/name"), "package-0\n")
+            .file(&format!("{base}/energy_uj"), "262143000000\n")
+            .file(&format!("{base}/max_energy_range_uj"), "262143328850\n");
+
+        let mut rapl = RaplMeasurement::with_root(root.path()).unwrap();
+        root.file(&format!("{base}/energy_uj"), "671150\n");
+        let energy = rapl.read_energy().unwrap();
+        assert!((energy.get::<joule>() - 1.0).abs() < 1e-9);
+
+        // Accumulation continues normally after the wrap
+        root.file(&format!("{base}/energy_uj"), "2671150\n");
+        let energy = rapl.read_energy().unwrap();
+        assert!((energy.get::<joule>() - 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_rapl_psys_only() {
+        let root = FakeRoot::new("rapl-psys");
+        let base = "sys/class/powercap/intel-rapl/intel-rapl:1";
+        root.file(&format!("{base}/name"), "psys\n")
+            .file(&format!("{base}/energy_uj"), "0\n");
+
+        let rapl = RaplMeasurement::with_root(root.path()).unwrap();
+        assert!(rapl.zones[0].counted);
+        // Without max_energy_range_uj wraps cannot be corrected
+        assert_eq!(rapl.zones[0].max_energy_range, None);
+    }
+
+    #[test]
+    fn test_rapl_missing_or_unparsable() {
+        let root = FakeRoot::new("rapl-missing");
+        assert!(matches!(
+            RaplMeasurement::with_root(root.path()),
+            Err(MeasurementError::RaplNotAvailable)
+        ));
+
+        // A zone without an energy counter is skipped
+        let base = "sys/class/powercap/intel-rapl/intel-rapl:0";
+        root.file(&format!("{base}/name"), "package-0\n");
+        assert!(matches!(
+            RaplMeasurement::with_root(root.path()),
+            Err(MeasurementError::RaplNotAvailable)
+        ));
+
+        root.file(&format!("{base}/energy_uj"), "garbage\n");
+        assert!(matches!(
+            RaplMeasurement::with_root(root.path()),
+            Err(MeasurementError::InvalidMeasurement(_))
+        ));
+    }
+
+    #[test]
+    fn test_auto_selection_with_root() {
+        let root = dual_socket_server();
+        let config = MeasurementConfig {
+            fs_root: root.path().to_path_buf(),
+            sample_interval_ms: 10,
+            ..Default::default()
+        };
+        let measurement = BenchmarkExecutor::new(config).measure(|| {}).unwrap();
+        assert_eq!(measurement.measurement_method, PowerSource::Rapl);
+        assert_eq!(measurement.domains.len(), 6);
+        let last = measurement.samples.last().unwrap();
+        assert_eq!(last.domains.len(), 6);
+        assert!(measurement
+            .samples
+            .windows(2)
+            .all(|pair| pair[0].offset <= pair[1].offset));
+
+        let root = laptop_power_now();
+        let config = MeasurementConfig {
+            min_duration: Some(Duration::from_millis(50)),
+            fs_root: root.path().to_path_buf(),
+            sample_interval_ms: 10,
+            ..Default::default()
+        };
+        let measurement = BenchmarkExecutor::new(config).measure(|| {}).unwrap();
+        assert_eq!(measurement.measurement_method, PowerSource::Acpi);
+        assert!((measurement.peak_power.get::<watt>() - 15.25).abs() < 1e-9);
+        assert!(!measurement.samples.is_empty());
+        assert!(measurement.samples[0].domains.is_empty());
+
+        let root = FakeRoot::new("bare");
+        let config = MeasurementConfig {
+            fs_root: root.path().to_path_buf(),
+            ..Default::default()
+        };
+        let measurement = BenchmarkExecutor::new(config).measure(|| {}).unwrap();
+        assert_eq!(measurement.measurement_method, PowerSource::TdpEstimate);
+    }
+
     #[test]
     fn test_rapl_availability() {
         let rapl_result = RaplMeasurement::new();
@@ -632,9 +2759,9 @@ mod tests {
     #[test]
     fn test_acpi_measurement() {
         let config = MeasurementConfig {
-            duration: Duration::from_secs(2),
             power_source: PowerSource::Acpi,
             sample_interval_ms: 100,
+            ..Default::default()
         };
 
         let executor = BenchmarkExecutor::new(config);
@@ -0,0 +1,250 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+use uom::si::energy::joule;
+use uom::si::f64::{Energy, Power};
+use uom::si::power::watt;
+
+use crate::procfs::{collect_descendants, read_process_rss, ProcessScan};
+use crate::{AttributionMode, MeasurementConfig, MeasurementError, WorkloadProcesses};
+
+/// DRAM power per GB of resident memory, from Cloud Carbon Footprint
+pub const DEFAULT_MEMORY_WATTS_PER_GB: f64 = 0.392;
+
+const BYTES_PER_GB: f64 = (1u64 << 30) as f64;
+
+/// Estimated energy of the memory used by the workload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemoryEnergy {
+    /// Energy of the resident memory over the measurement
+    pub energy: Energy,
+    /// Average power of the resident memory
+    pub average_power: Power,
+    /// Resident memory averaged over time, in bytes
+    pub average_bytes: u64,
+    /// Largest resident memory sampled, in bytes
+    pub peak_bytes: u64,
+    /// Coefficient the energy was estimated with
+    pub watts_per_gb: f64,
+}
+
+/// Where the resident memory of the workload is read from
+enum ResidentSource {
+    /// Sum of `VmRSS` over the workload processes of `pid`; pages shared
+    /// between them are counted once per process
+    ProcessTree {
+        root: PathBuf,
+        pid: u32,
+        processes: WorkloadProcesses,
+    },
+    /// `memory.current` of a cgroup v2, which includes the page cache
+    Cgroup(PathBuf),
+}
+
+/// Estimates memory energy by integrating resident memory over time
+pub(crate) struct MemoryModel {
+    source: ResidentSource,
+    watts_per_gb: f64,
+    start_time: Instant,
+    last_time: Instant,
+    last_bytes: u64,
+    peak_bytes: u64,
+    /// Integral of resident bytes over seconds
+    byte_seconds: f64,
+    /// Why the workload is followed differently than attributed
+    warning: Option<String>,
+}
+
+impl MemoryModel {
+    /// Opens the model when `config.memory_watts_per_gb` is set, following
+    /// the same workload as `config.attribution`
+    pub(crate) fn open(config: &MeasurementConfig) -> Result<Option<Self>, MeasurementError> {
+        let Some(watts_per_gb) = config.memory_watts_per_gb else {
+            return Ok(None);
+        };
+        let process_tree = ResidentSource::ProcessTree {
+            root: config.fs_root.clone(),
+            pid: std::process::id(),
+            processes: config.workload_processes,
+        };
+        let (source, warning) = match &config.attribution {
+            // memory.current is missing without the memory controller
+            AttributionMode::Cgroup(path) if path.join("memory.current").exists() => {
+                (ResidentSource::Cgroup(path.clone()), None)
+            }
+            AttributionMode::Cgroup(path) => (
+                process_tree,
+                Some(format!(
+                    "the memory controller is not enabled for {}, so memory is read by process tree",
+                    path.display()
+                )),
+            ),
+            _ => (process_tree, None),
+        };
+        let mut model = Self::new(source, watts_per_gb)?;
+        model.warning = warning;
+        Ok(Some(model))
+    }
+
+    fn new(source: ResidentSource, watts_per_gb: f64) -> Result<Self, MeasurementError> {
+        let bytes = read_resident_bytes(&source, &ProcessScan::default())?;
+        let now = Instant::now();
+        Ok(Self {
+            source,
+            watts_per_gb,
+            start_time: now,
+            last_time: now,
+            last_bytes: bytes,
+            peak_bytes: bytes,
+            byte_seconds: 0.0,
+            warning: None,
+        })
+    }
+
+    /// Reads the resident memory, finding processes in `scan`
+    pub(crate) fn sample(&mut self, scan: &ProcessScan) -> Result<(), MeasurementError> {
+        let bytes = read_resident_bytes(&self.source, scan)?;
+        self.record(bytes, Instant::now());
+        Ok(())
+    }
+
+    fn record(&mut self, bytes: u64, now: Instant) {
+        let seconds = now.duration_since(self.last_time).as_secs_f64();
+        // Trapezoidal rule between consecutive samples
+        self.byte_seconds += (self.last_bytes + bytes) as f64 / 2.0 * seconds;
+        self.peak_bytes = self.peak_bytes.max(bytes);
+        self.last_bytes = bytes;
+        self.last_time = now;
+    }
+
+    /// Why memory is not read from the cgroup the energy is attributed by
+    pub(crate) fn warning(&self) -> Option<&str> {
+        self.warning.as_deref()
+    }
+
+    /// The memory energy from the samples so far
+    pub(crate) fn finish(&self) -> MemoryEnergy {
+        let seconds = self.last_time.duration_since(self.start_time).as_secs_f64();
+        let joules = self.byte_seconds / BYTES_PER_GB * self.watts_per_gb;
+        let (average_bytes, average_watts) = if seconds > 0.0 {
+            (self.byte_seconds / seconds, joules / seconds)
+        } else {
+            (
+                self.last_bytes as f64,
+                self.last_bytes as f64 / BYTES_PER_GB * self.watts_per_gb,
+            )
+        };
+        MemoryEnergy {
+            energy: Energy::new::<joule>(joules),
+            average_power: Power::new::<watt>(average_watts),
+            average_bytes: average_bytes as u64,
+            peak_bytes: self.peak_bytes,
+            watts_per_gb: self.watts_per_gb,
+        }
+    }
+}
+
+fn read_resident_bytes(
+    source: &ResidentSource,
+    scan: &ProcessScan,
+) -> Result<u64, MeasurementError> {
+    match source {
+        ResidentSource::ProcessTree {
+            root,
+            pid,
+            processes,
+        } => {
+            let mut members = vec![*pid];
+            collect_descendants(scan.processes(root)?, &mut members);
+            if *processes == WorkloadProcesses::Children {
+                members.remove(0);
+            }
+            Ok(members
+                .into_iter()
+                // Processes may exit after the tree was read
+                .filter_map(|pid| read_process_rss(root, pid).ok())
+                .sum())
+        }
+        ResidentSource::Cgroup(path) => read_memory_current(path),
+    }
+}
+
+fn read_memory_current(path: &Path) -> Result<u64, MeasurementError> {
+    fs::read_to_string(path.join("memory.current"))?
+        .trim()
+        .parse()
+        .map_err(|_| {
+            MeasurementError::InvalidMeasurement("Failed to parse memory.current".to_string())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::FakeRoot;
+    use std::time::Duration;
+
+    fn process(root: &FakeRoot, pid: u32, ppid: u32, rss_kb: u64) {
+        root.file(
+            &format!("proc/{pid}/stat"),
+            &format!("{pid} (worker) S {ppid} 1 1 0 -1 0 0 0 0 0 0 0 0 0 20 0 1 0 1 0 0"),
+        )
+        .file(
+            &format!("proc/{pid}/status"),
+            &format!("Name:\tworker\nVmRSS:\t{rss_kb} kB\n"),
+        );
+    }
+
+    #[test]
+    fn test_process_tree_memory() {
+        let root = FakeRoot::new("memory-tree");
+        process(&root, 100, 1, 512 * 1024);
+        process(&root, 200, 100, 512 * 1024);
+        process(&root, 300, 1, 4096 * 1024);
+        let source = |processes| ResidentSource::ProcessTree {
+            root: root.path().to_path_buf(),
+            pid: 100,
+            processes,
+        };
+
+        let children = MemoryModel::new(source(WorkloadProcesses::Children), 0.5).unwrap();
+        assert_eq!(children.last_bytes, 512 << 20);
+        let mut model = MemoryModel::new(source(WorkloadProcesses::Current), 0.5).unwrap();
+        assert_eq!(model.last_bytes, 1 << 30);
+
+        // 1 GB rising to 3 GB over 10 s averages 2 GB
+        let later = model.start_time + Duration::from_secs(10);
+        model.record(3 << 30, later);
+        let memory = model.finish();
+        assert_eq!(memory.energy.get::<joule>(), 10.0);
+        assert_eq!(memory.average_power.get::<watt>(), 1.0);
+        assert_eq!(memory.average_bytes, 2 << 30);
+        assert_eq!(memory.peak_bytes, 3 << 30);
+    }
+
+    #[test]
+    fn test_cgroup_memory() {
+        let root = FakeRoot::new("memory-cgroup");
+        root.file("sys/fs/cgroup/job/memory.current", "268435456\n");
+        let source = ResidentSource::Cgroup(root.path().join("sys/fs/cgroup/job"));
+        let model = MemoryModel::new(source, DEFAULT_MEMORY_WATTS_PER_GB).unwrap();
+        assert_eq!(model.last_bytes, 256 << 20);
+    }
+
+    #[test]
+    fn test_cgroup_without_memory_controller() {
+        let cgroup = FakeRoot::new("memory-no-controller");
+        let config = MeasurementConfig {
+            memory_watts_per_gb: Some(DEFAULT_MEMORY_WATTS_PER_GB),
+            attribution: AttributionMode::Cgroup(cgroup.path().to_path_buf()),
+            ..Default::default()
+        };
+        let model = MemoryModel::open(&config).unwrap().unwrap();
+        assert!(matches!(model.source, ResidentSource::ProcessTree { .. }));
+        assert!(model.warning().unwrap().contains("memory controller"));
+    }
+}
@@ -0,0 +1,611 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{oneshot, watch};
+use tokio::task;
+use tokio::time::{self, MissedTickBehavior};
+use uom::si::f64::{Energy, Power, Time};
+use uom::si::{energy::joule, power::watt, time::second};
+
+use crate::attribution::WorkloadTracker;
+use crate::memory::MemoryModel;
+use crate::network::NetworkCounter;
+use crate::phase::PhaseMarkers;
+use crate::procfs::ProcessScan;
+use crate::region::Regions;
+use crate::storage::StorageCounter;
+use crate::{
+    DomainEnergy, DomainPower, EnergyMeasurement, MeasurementError, PowerSample, PowerSource,
+};
+
+/// Describes what a [`PowerMeter`] is able to report
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeterCapabilities {
+    /// The meter reports instantaneous power via [`PowerMeter::read_power`]
+    pub instantaneous_power: bool,
+    /// The meter reports cumulative energy via [`PowerMeter::read_energy`]
+    pub cumulative_energy: bool,
+    /// Smallest power step the meter can resolve, if known
+    pub power_resolution: Option<Power>,
+    /// Smallest energy step the meter can resolve, if known
+    pub energy_resolution: Option<Energy>,
+}
+
+/// Cumulative energy of a single domain of a [`PowerMeter`]
+#[derive(Debug, Clone)]
+pub struct DomainReading {
+    /// Domain name, e.g. `package-0` or `package-0/dram`
+    pub name: String,
+    /// Energy consumed by the domain since the meter was created
+    pub energy: Energy,
+    /// Whether the domain is part of the meter's total energy
+    pub included_in_total: bool,
+}
+
+/// A source of power and/or energy readings
+///
+/// Implementations report at least one of instantaneous power or cumulative
+/// energy, as advertised by [`PowerMeter::capabilities`]. The
+/// [`BenchmarkExecutor`](crate::BenchmarkExecutor) samples the meter on a
+/// background thread while the workload runs.
+pub trait PowerMeter: Send {
+    /// The method reported in [`EnergyMeasurement::measurement_method`]
+    fn source(&self) -> PowerSource {
+        PowerSource::Custom
+    }
+
+    /// Describes which readings the meter supports
+    fn capabilities(&self) -> MeterCapabilities;
+
+    /// Reads the current power draw
+    fn read_power(&mut self) -> Result<Power, MeasurementError> {
+        Err(MeasurementError::Unsupported(
+            "instantaneous power".to_string(),
+        ))
+    }
+
+    /// Reads the energy consumed since the meter was created
+    ///
+    /// The value must be monotonic, so counters that wrap have to be
+    /// corrected by the implementation.
+    fn read_energy(&mut self) -> Result<Energy, MeasurementError> {
+        Err(MeasurementError::Unsupported(
+            "cumulative energy".to_string(),
+        ))
+    }
+
+    /// Per-domain energy as of the most recent [`PowerMeter::read_energy`]
+    fn domains(&self) -> Vec<DomainReading> {
+        Vec::new()
+    }
+
+    /// Adds meter-specific details to a finished measurement
+    fn annotate(&self, _measurement: &mut EnergyMeasurement) {}
+}
+
+/// An error while sampling, handing back the meter so that it can be
+/// reused
+pub(crate) struct MeterError {
+    pub(crate) error: MeasurementError,
+    /// Unset when the meter was lost to a panic in the sampling thread
+    pub(crate) meter: Option<Box<dyn PowerMeter>>,
+}
+
+impl MeterError {
+    fn new(error: MeasurementError, meter: Box<dyn PowerMeter>) -> Self {
+        Self {
+            error,
+            meter: Some(meter),
+        }
+    }
+
+    fn panicked(what: &str) -> Self {
+        Self {
+            error: MeasurementError::InvalidMeasurement(format!("{what} panicked")),
+            meter: None,
+        }
+    }
+}
+
+/// Where a user-supplied meter is kept between measurements
+pub(crate) type MeterSlot = Arc<Mutex<Option<Box<dyn PowerMeter>>>>;
+
+/// Periodically reads a [`PowerMeter`] and turns the readings into an
+/// [`EnergyMeasurement`]
+pub(crate) struct Sampler {
+    meter: Box<dyn PowerMeter>,
+    capabilities: MeterCapabilities,
+    start_time: Instant,
+    last_time: Instant,
+    start_energy: Energy,
+    last_energy: Energy,
+    start_domains: Vec<DomainReading>,
+    last_domains: Vec<DomainReading>,
+    /// Power over the run
+    samples: Vec<PowerSample>,
+    /// CPU time of the workload, sampled along with the meter
+    tracker: Option<WorkloadTracker>,
+    /// Resident memory of the workload, sampled along with the meter
+    memory: Option<MemoryModel>,
+    /// Interface byte counters at the start
+    network: Option<NetworkCounter>,
+    /// Storage I/O of the workload, sampled along with the meter
+    storage: Option<StorageCounter>,
+    /// Phases marked while sampling
+    markers: PhaseMarkers,
+    /// Regions entered while sampling
+    regions: Regions,
+}
+
+impl Sampler {
+    /// Takes the initial reading
+    pub(crate) fn start(mut meter: Box<dyn PowerMeter>) -> Result<Self, MeterError> {
+        let capabilities = meter.capabilities();
+        let (start_energy, start_power) = match initial_reading(&mut *meter, capabilities) {
+            Ok(reading) => reading,
+            Err(error) => return Err(MeterError::new(error, meter)),
+        };
+        let start_domains = meter.domains();
+        let start_time = Instant::now();
+        // Meters that only report power are integrated from the start
+        let samples = start_power
+            .map(|power| PowerSample {
+                offset: Duration::ZERO,
+                power,
+                domains: Vec::new(),
+            })
+            .into_iter()
+            .collect();
+
+        Ok(Self {
+            meter,
+            capabilities,
+            start_time,
+            last_time: start_time,
+            start_energy,
+            last_energy: start_energy,
+            last_domains: start_domains.clone(),
+            start_domains,
+            samples,
+            tracker: None,
+            memory: None,
+            network: None,
+            storage: None,
+            markers: PhaseMarkers::default(),
+            regions: Regions::default(),
+        })
+    }
+
+    /// Attributes the measured energy to the workload followed by
+    /// `tracker`, if any
+    pub(crate) fn with_tracker(mut self, tracker: Option<WorkloadTracker>) -> Self {
+        self.tracker = tracker;
+        self
+    }
+
+    /// Estimates memory energy with `memory`, if any
+    pub(crate) fn with_memory(mut self, memory: Option<MemoryModel>) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    /// Reports the data transferred since `network` was started, if any
+    pub(crate) fn with_network(mut self, network: Option<NetworkCounter>) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Estimates storage I/O energy with `storage`, if any
+    pub(crate) fn with_storage(mut self, storage: Option<StorageCounter>) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    /// Splits the measurement into the phases marked with `markers`
+    pub(crate) fn with_markers(mut self, markers: PhaseMarkers) -> Self {
+        self.markers = markers;
+        self
+    }
+
+    /// Reports the energy of the regions entered through `regions`
+    pub(crate) fn with_regions(mut self, regions: Regions) -> Self {
+        self.regions = regions;
+        self
+    }
+
+    /// Takes a single reading
+    pub(crate) fn sample(&mut self) -> Result<(), MeasurementError> {
+        // The probes following a process tree share one read of /proc
+        let scan = ProcessScan::default();
+        if let Some(tracker) = &mut self.tracker {
+            // Missing a process is corrected by its parent's next reading
+            let _ = tracker.sample(&scan);
+        }
+        if let Some(memory) = &mut self.memory {
+            // A failed reading extends the previous one to the next
+            let _ = memory.sample(&scan);
+        }
+        if let Some(storage) = &mut self.storage {
+            // I/O of processes missed here is kept by their parents
+            let _ = storage.sample(&scan);
+        }
+        let mut domains = Vec::new();
+        let watts = if self.capabilities.cumulative_energy {
+            let energy = self.meter.read_energy()?;
+            let now = Instant::now();
+            let elapsed = now.duration_since(self.last_time).as_secs_f64();
+            let interval_energy = (energy - self.last_energy).get::<joule>();
+            self.last_energy = energy;
+            self.last_time = now;
+
+            let readings = self.meter.domains();
+            if elapsed > 0.0 {
+                domains = readings
+                    .iter()
+                    .map(|domain| DomainPower {
+                        name: domain.name.clone(),
+                        power: (domain.energy - start_energy(&self.last_domains, &domain.name))
+                            / Time::new::<second>(elapsed),
+                    })
+                    .collect();
+            }
+            self.last_domains = readings;
+
+            if self.capabilities.instantaneous_power {
+                self.meter.read_power()?.get::<watt>()
+            } else if elapsed > 0.0 {
+                interval_energy / elapsed
+            } else {
+                return Ok(());
+            }
+        } else {
+            let power = self.meter.read_power()?;
+            self.last_time = Instant::now();
+            power.get::<watt>()
+        };
+
+        self.samples.push(PowerSample {
+            offset: self.last_time.duration_since(self.start_time),
+            power: Power::new::<watt>(watts),
+            domains,
+        });
+        self.regions.settle(
+            self.start_time,
+            &self.samples,
+            !self.capabilities.cumulative_energy,
+        );
+        Ok(())
+    }
+
+    /// Samples on the current thread until `done` is set and `min` has
+    /// passed, or until `max` has passed
+    pub(crate) fn run(
+        mut self,
+        interval: Duration,
+        min: Option<Duration>,
+        max: Option<Duration>,
+        done: &AtomicBool,
+    ) -> Result<Self, MeterError> {
+        let limits = Limits::new(self.start_time, min, max);
+        let finished = || limits.reached(done.load(Ordering::Acquire));
+
+        while !finished() {
+            thread::park_timeout(limits.next_wakeup(interval));
+            if finished() {
+                break;
+            }
+            // A failed intermediate reading is covered by the next one
+            let _ = self.sample();
+        }
+        self.final_sample()
+    }
+
+    /// Samples on every tick of a tokio interval until `done` is set and
+    /// `min` has passed, or until `max` has passed
+    ///
+    /// Readings go through `/proc`, sysfs and perf, so they are taken on the
+    /// blocking thread pool rather than a runtime worker.
+    pub(crate) async fn run_async(
+        mut self,
+        interval: Duration,
+        min: Option<Duration>,
+        max: Option<Duration>,
+        mut done: watch::Receiver<bool>,
+    ) -> Result<Self, MeterError> {
+        let limits = Limits::new(self.start_time, min, max);
+        let mut ticks = time::interval(interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // The first tick completes immediately
+        ticks.tick().await;
+
+        while !limits.reached(*done.borrow()) {
+            tokio::select! {
+                _ = ticks.tick() => {}
+                // A dropped sender has already set the flag
+                Ok(()) = done.changed() => {}
+                _ = time::sleep(limits.next_wakeup(interval)) => {}
+            }
+            if limits.reached(*done.borrow()) {
+                break;
+            }
+            self = task::spawn_blocking(move || {
+                // A failed intermediate reading is covered by the next one
+                let _ = self.sample();
+                self
+            })
+            .await
+            .map_err(|_| MeterError::panicked("sampling task"))?;
+        }
+        task::spawn_blocking(move || self.final_sample())
+            .await
+            .unwrap_or_else(|_| Err(MeterError::panicked("sampling task")))
+    }
+
+    /// Takes the last reading; meters that only report power keep the
+    /// readings they have
+    fn final_sample(mut self) -> Result<Self, MeterError> {
+        match self.sample() {
+            Err(error) if self.capabilities.cumulative_energy => {
+                Err(MeterError::new(error, self.meter))
+            }
+            _ => Ok(self),
+        }
+    }
+
+    /// Runs [`Sampler::run`] on a background thread
+    pub(crate) fn spawn(
+        self,
+        interval: Duration,
+        min: Option<Duration>,
+        max: Option<Duration>,
+    ) -> SamplerHandle {
+        let done = Arc::new(AtomicBool::new(false));
+        let thread = thread::spawn({
+            let done = done.clone();
+            move || self.run(interval, min, max, &done)
+        });
+        SamplerHandle { thread, done }
+    }
+
+    /// Runs [`Sampler::run_async`] as a task on the current tokio runtime
+    ///
+    /// If the [`SamplerTask`] is dropped before it is stopped, the task puts
+    /// the meter into `home` once it finishes.
+    pub(crate) fn spawn_task(
+        self,
+        interval: Duration,
+        min: Option<Duration>,
+        max: Option<Duration>,
+        home: Option<MeterSlot>,
+    ) -> SamplerTask {
+        let (done, receiver) = watch::channel(false);
+        let (sender, result) = oneshot::channel();
+        tokio::spawn(async move {
+            let sampled = self.run_async(interval, min, max, receiver).await;
+            if let (Err(sampled), Some(home)) = (sender.send(sampled), home) {
+                let meter = match sampled {
+                    Ok(sampler) => Some(sampler.meter),
+                    Err(error) => error.meter,
+                };
+                if meter.is_some() {
+                    *home.lock().unwrap_or_else(PoisonError::into_inner) = meter;
+                }
+            }
+        });
+        SamplerTask { result, done }
+    }
+
+    /// Builds the measurement, handing the meter back for reuse
+    pub(crate) fn finish(mut self) -> (EnergyMeasurement, Box<dyn PowerMeter>) {
+        let measurement_method = self.meter.source();
+        let peak_power = self
+            .samples
+            .iter()
+            .map(|sample| sample.power.get::<watt>())
+            .fold(0.0, f64::max);
+
+        let duration = self.last_time.duration_since(self.start_time);
+        let total_energy = if self.capabilities.cumulative_energy {
+            self.last_energy - self.start_energy
+        } else {
+            // Trapezoidal rule between consecutive readings
+            let joules = self
+                .samples
+                .windows(2)
+                .map(|pair| {
+                    let seconds = (pair[1].offset - pair[0].offset).as_secs_f64();
+                    (pair[0].power + pair[1].power).get::<watt>() / 2.0 * seconds
+                })
+                .sum();
+            Energy::new::<joule>(joules)
+        };
+
+        let seconds = duration.as_secs_f64();
+        let average_power = if seconds > 0.0 {
+            Power::new::<watt>(total_energy.get::<joule>() / seconds)
+        } else {
+            // A single reading is all there is to go by
+            self.samples
+                .last()
+                .map(|sample| sample.power)
+                .unwrap_or_default()
+        };
+        let domains = self
+            .meter
+            .domains()
+            .into_iter()
+            .map(|domain| {
+                let energy = domain.energy - start_energy(&self.start_domains, &domain.name);
+                DomainEnergy {
+                    name: domain.name,
+                    average_power: Power::new::<watt>(energy.get::<joule>() / seconds),
+                    energy,
+                    included_in_total: domain.included_in_total,
+                }
+            })
+            .collect();
+
+        let warnings = self
+            .memory
+            .as_ref()
+            .and_then(MemoryModel::warning)
+            .into_iter()
+            .chain(self.storage.as_ref().and_then(StorageCounter::warning))
+            .map(str::to_string)
+            .collect();
+        let mut measurement = EnergyMeasurement {
+            total_energy,
+            average_power,
+            peak_power: Power::new::<watt>(peak_power),
+            duration,
+            measurement_method,
+            domains,
+            cpu_profile: None,
+            attribution: self
+                .tracker
+                .as_ref()
+                .map(|tracker| tracker.attribute(total_energy)),
+            memory: self.memory.as_ref().map(MemoryModel::finish),
+            // Unreadable counters leave the transfer unknown rather than
+            // failing the measurement
+            network: self
+                .network
+                .as_ref()
+                .and_then(|network| network.finish().ok()),
+            storage: self
+                .storage
+                .as_mut()
+                .and_then(|storage| storage.finish().ok()),
+            baseline: None,
+            phases: self.markers.phases(
+                self.start_time,
+                duration,
+                &self.samples,
+                !self.capabilities.cumulative_energy,
+            ),
+            regions: self.regions.tree(
+                self.start_time,
+                duration,
+                &self.samples,
+                !self.capabilities.cumulative_energy,
+            ),
+            samples: self.samples,
+            batteries: Vec::new(),
+            power_supplies: Vec::new(),
+            warnings,
+        };
+        self.meter.annotate(&mut measurement);
+        (measurement, self.meter)
+    }
+}
+
+/// The energy a measurement starts from, and the power of the first
+/// sample for meters that only report power
+fn initial_reading(
+    meter: &mut dyn PowerMeter,
+    capabilities: MeterCapabilities,
+) -> Result<(Energy, Option<Power>), MeasurementError> {
+    if capabilities.cumulative_energy {
+        Ok((meter.read_energy()?, None))
+    } else if capabilities.instantaneous_power {
+        Ok((Energy::new::<joule>(0.0), Some(meter.read_power()?)))
+    } else {
+        Err(MeasurementError::Unsupported(
+            "meter reports neither power nor energy".to_string(),
+        ))
+    }
+}
+
+/// Energy of the domain called `name` in `readings`, zero for domains that
+/// appeared since
+fn start_energy(readings: &[DomainReading], name: &str) -> Energy {
+    readings
+        .iter()
+        .find(|reading| reading.name == name)
+        .map(|reading| reading.energy)
+        .unwrap_or_default()
+}
+
+/// When a [`Sampler`] may stop
+struct Limits {
+    start_time: Instant,
+    min: Option<Duration>,
+    max: Option<Duration>,
+}
+
+impl Limits {
+    fn new(start_time: Instant, min: Option<Duration>, max: Option<Duration>) -> Self {
+        Self {
+            start_time,
+            min,
+            max,
+        }
+    }
+
+    /// Whether to stop, given whether the workload is done
+    fn reached(&self, done: bool) -> bool {
+        let elapsed = self.start_time.elapsed();
+        (done && self.min.is_none_or(|min| elapsed >= min))
+            || self.max.is_some_and(|max| elapsed >= max)
+    }
+
+    /// Time until the next reading, waking up in time for whichever limit
+    /// comes first
+    fn next_wakeup(&self, interval: Duration) -> Duration {
+        let elapsed = self.start_time.elapsed();
+        [self.min, self.max]
+            .into_iter()
+            .flatten()
+            .filter(|limit| *limit > elapsed)
+            .map(|limit| limit - elapsed)
+            .fold(interval, Duration::min)
+    }
+}
+
+/// A [`Sampler`] running on a background thread
+pub(crate) struct SamplerHandle {
+    thread: thread::JoinHandle<Result<Sampler, MeterError>>,
+    done: Arc<AtomicBool>,
+}
+
+impl SamplerHandle {
+    /// Stops sampling and waits for the final reading
+    pub(crate) fn stop(self) -> Result<Sampler, MeterError> {
+        self.done.store(true, Ordering::Release);
+        self.thread.thread().unpark();
+        self.thread
+            .join()
+            .unwrap_or_else(|_| Err(MeterError::panicked("sampling thread")))
+    }
+}
+
+/// A [`Sampler`] running as a tokio task
+///
+/// Dropping the handle stops the task, which then returns the meter to the
+/// home it was spawned with, or drops it.
+pub(crate) struct SamplerTask {
+    result: oneshot::Receiver<Result<Sampler, MeterError>>,
+    done: watch::Sender<bool>,
+}
+
+impl SamplerTask {
+    /// Stops sampling and waits for the final reading
+    pub(crate) async fn stop(mut self) -> Result<Sampler, MeterError> {
+        self.done.send_replace(true);
+        (&mut self.result)
+            .await
+            .unwrap_or_else(|_| Err(MeterError::panicked("sampling task")))
+    }
+}
+
+impl Drop for SamplerTask {
+    fn drop(&mut self) {
+        self.done.send_replace(true);
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use uom::si::f64::Energy;
+
+use crate::{
+    gigabytes_to_kwh, joules_to_kwh, kwh_to_co2e, kwh_to_joules, MeasurementConfig,
+    MeasurementError,
+};
+
+/// Which traffic the network counters cover
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkScope {
+    /// The interfaces of the workload's network namespace
+    Namespace,
+    /// All interfaces of the system
+    System,
+}
+
+impl Display for NetworkScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetworkScope::Namespace => write!(f, "namespace"),
+            NetworkScope::System => write!(f, "system"),
+        }
+    }
+}
+
+/// Data transferred during the measurement and its estimated energy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkEnergy {
+    /// Bytes received, excluding loopback
+    pub received_bytes: u64,
+    /// Bytes sent, excluding loopback
+    pub sent_bytes: u64,
+    /// Energy of the transfer from [`gigabytes_to_kwh`]
+    pub energy: Energy,
+    /// Which traffic the byte counts cover
+    pub scope: NetworkScope,
+}
+
+impl NetworkEnergy {
+    /// Converts the transfer energy to grams of CO2e
+    pub fn co2e(&self, co2e_per_kwh: Option<f64>) -> f64 {
+        kwh_to_co2e(joules_to_kwh(self.energy), co2e_per_kwh.unwrap_or(436.0))
+    }
+}
+
+/// Byte counters of the network interfaces at the start of a measurement
+pub(crate) struct NetworkCounter {
+    path: PathBuf,
+    scope: NetworkScope,
+    start: HashMap<String, (u64, u64)>,
+}
+
+impl NetworkCounter {
+    /// Starts counting when `config.network` is set, in the network
+    /// namespace of this process, which spawned commands inherit
+    pub(crate) fn open(config: &MeasurementConfig) -> Result<Option<Self>, MeasurementError> {
+        match config.network {
+            true => Self::start(&config.fs_root, std::process::id()).map(Some),
+            false => Ok(None),
+        }
+    }
+
+    /// Reads `proc/<pid>/net/dev` below `root`, or the system-wide
+    /// `proc/net/dev` when that is not readable
+    ///
+    /// In the network namespace of init the interfaces are those of the
+    /// whole system, so the scope is only a namespace when `pid` is known
+    /// to be in another one. Unprivileged users cannot read the namespace
+    /// of init, and most of them run in it.
+    pub(crate) fn start(root: impl AsRef<Path>, pid: u32) -> Result<Self, MeasurementError> {
+        let root = root.as_ref();
+        let namespace = root.join("proc").join(pid.to_string()).join("net/dev");
+        let (path, scope) = if !namespace.exists() {
+            (root.join("proc/net/dev"), NetworkScope::System)
+        } else if in_init_namespace(root, pid) == Some(false) {
+            (namespace, NetworkScope::Namespace)
+        } else {
+            (namespace, NetworkScope::System)
+        };
+        let start = read_net_dev(&path)?;
+        Ok(Self { path, scope, start })
+    }
+
+    /// Bytes transferred since the start
+    pub(crate) fn finish(&self) -> Result<NetworkEnergy, MeasurementError> {
+        let mut received_bytes = 0;
+        let mut sent_bytes = 0;
+        for (interface, (received, sent)) in read_net_dev(&self.path)? {
+            // Interfaces that appeared during the run count from zero
+            let (start_received, start_sent) =
+                self.start.get(&interface).copied().unwrap_or_default();
+            received_bytes += received.saturating_sub(start_received);
+            sent_bytes += sent.saturating_sub(start_sent);
+        }
+        let gigabytes = (received_bytes + sent_bytes) as f64 / 1e9;
+        Ok(NetworkEnergy {
+            received_bytes,
+            sent_bytes,
+            energy: kwh_to_joules(gigabytes_to_kwh(gigabytes)),
+            scope: self.scope,
+        })
+    }
+}
+
+/// Whether `pid` shares the network namespace of init, or `None` when
+/// either namespace cannot be read
+fn in_init_namespace(root: &Path, pid: u32) -> Option<bool> {
+    let inode = |pid: &str| {
+        fs::metadata(root.join("proc").join(pid).join("ns/net"))
+            .map(|metadata| metadata.ino())
+            .ok()
+    };
+    Some(inode(&pid.to_string())? == inode("1")?)
+}
+
+/// Reads received and sent bytes per interface, except loopback
+fn read_net_dev(path: &Path) -> Result<HashMap<String, (u64, u64)>, MeasurementError> {
+    let dev = fs::read_to_string(path)?;
+    let mut interfaces = HashMap::new();
+    // The first two lines are column headers
+    for line in dev.lines().skip(2) {
+        let Some((interface, counters)) = line.split_once(':') else {
+            continue;
+        };
+        let interface = interface.trim();
+        if interface == "lo" {
+            continue;
+        }
+        let counters: Vec<u64> = counters
+            .split_whitespace()
+            .map(|counter| counter.parse().unwrap_or(0))
+            .collect();
+        // 8 receive columns are followed by 8 transmit columns
+        if counters.len() < 16 {
+            return Err(MeasurementError::InvalidMeasurement(format!(
+                "Failed to parse {}",
+                path.display()
+            )));
+        }
+        interfaces.insert(interface.to_string(), (counters[0], counters[8]));
+    }
+    Ok(interfaces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::FakeRoot;
+    use uom::si::energy::kilowatt_hour;
+
+    fn net_dev(eth0: (u64, u64), lo: u64) -> String {
+        format!(
+            "Inter-|   Receive                                                |  Transmit\n \
+             face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed\n    \
+             lo: {lo} 10 0 0 0 0 0 0 {lo} 10 0 0 0 0 0 0\n  \
+             eth0: {} 100 0 0 0 0 0 0 {} 50 0 0 0 0 0 0\n",
+            eth0.0, eth0.1
+        )
+    }
+
+    #[test]
+    fn test_namespace_transfer() {
+        let root = FakeRoot::new("net-namespace");
+        root.file("proc/42/net/dev", &net_dev((1_000, 2_000), 5_000))
+            .file("proc/42/ns/net", "")
+            .file("proc/1/ns/net", "")
+            .file("proc/net/dev", &net_dev((0, 0), 0));
+
+        let counter = NetworkCounter::start(root.path(), 42).unwrap();
+        root.file(
+            "proc/42/net/dev",
+            &net_dev((1_000_001_000, 1_000_002_000), 9_000_000_000),
+        );
+        let network = counter.finish().unwrap();
+        assert_eq!(network.scope, NetworkScope::Namespace);
+        assert_eq!(network.received_bytes, 1_000_000_000);
+        assert_eq!(network.sent_bytes, 1_000_000_000);
+        assert!((network.energy.get::<kilowatt_hour>() - 0.005625).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_system_fallback() {
+        let root = FakeRoot::new("net-system");
+        root.file("proc/net/dev", &net_dev((10, 20), 0));
+        let counter = NetworkCounter::start(root.path(), 42).unwrap();
+        assert_eq!(counter.scope, NetworkScope::System);
+
+        root.file("proc/net/dev", "garbage\nheader\n  eth0: 1 2 3\n");
+        assert!(counter.finish().is_err());
+
+        // Sharing the namespace of init counts the whole system as well
+        root.file("proc/42/net/dev", &net_dev((10, 20), 0))
+            .file("proc/1/ns/net", "");
+        fs::create_dir_all(root.path().join("proc/42/ns")).unwrap();
+        std::os::unix::fs::symlink(
+            root.path().join("proc/1/ns/net"),
+            root.path().join("proc/42/ns/net"),
+        )
+        .unwrap();
+        let counter = NetworkCounter::start(root.path(), 42).unwrap();
+        assert_eq!(counter.scope, NetworkScope::System);
+    }
+
+    #[test]
+    fn test_unknown_namespace() {
+        // Without access to the namespace of init, as for a non-root user
+        let root = FakeRoot::new("net-unknown");
+        root.file("proc/42/net/dev", &net_dev((10, 20), 0))
+            .file("proc/42/ns/net", "");
+        let counter = NetworkCounter::start(root.path(), 42).unwrap();
+        assert_eq!(counter.scope, NetworkScope::System);
+        assert_eq!(in_init_namespace(root.path(), 42), None);
+    }
+}
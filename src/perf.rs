@@ -0,0 +1,476 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::Path,
+};
+
+use uom::si::energy::joule;
+use uom::si::f64::Energy;
+
+use crate::{DomainReading, MeasurementError, MeterCapabilities, PowerMeter, PowerSource};
+
+/// An energy event of the `power` PMU, e.g. `energy-pkg`
+#[derive(Debug, Clone, PartialEq)]
+struct PerfEventSpec {
+    /// Event name from the `events` directory
+    name: String,
+    /// Value of the `event=` term, used as `perf_event_attr.config`
+    config: u64,
+    /// Joules per counter increment, from the `.scale` file
+    scale: f64,
+}
+
+/// The `power` PMU as described below `/sys/bus/event_source/devices/power`
+#[derive(Debug, Clone, PartialEq)]
+struct PowerPmu {
+    /// Dynamic PMU type used as `perf_event_attr.type`
+    pmu_type: u32,
+    events: Vec<PerfEventSpec>,
+    /// One CPU per package, from the `cpumask` file
+    cpus: Vec<i32>,
+}
+
+impl PowerPmu {
+    fn discover(root: &Path) -> Result<Self, MeasurementError> {
+        let base_path = root.join("sys/bus/event_source/devices/power");
+        let read = |path: &Path| {
+            fs::read_to_string(path)
+                .map(|value| value.trim().to_string())
+                .map_err(|_| MeasurementError::PerfNotAvailable)
+        };
+
+        let pmu_type = read(&base_path.join("type"))?
+            .parse()
+            .map_err(|_| MeasurementError::PerfNotAvailable)?;
+        let cpus = parse_cpu_list(&read(&base_path.join("cpumask"))?)
+            .ok_or(MeasurementError::PerfNotAvailable)?;
+
+        let entries = fs::read_dir(base_path.join("events"))
+            .map_err(|_| MeasurementError::PerfNotAvailable)?;
+        let mut events = Vec::new();
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            let Some(name) = file_name.to_str() else {
+                continue;
+            };
+            // Skip the .scale and .unit companions of each event
+            if !name.starts_with("energy-") || name.contains('.') {
+                continue;
+            }
+
+            let Some(config) = read(&entry.path())
+                .ok()
+                .and_then(|c| parse_event_config(&c))
+            else {
+                continue;
+            };
+            let events_path = base_path.join("events");
+            // Counts are only meaningful when they are scaled to Joules
+            if read(&events_path.join(format!("{name}.unit")))
+                .ok()
+                .as_deref()
+                != Some("Joules")
+            {
+                continue;
+            }
+            let Some(scale) = read(&events_path.join(format!("{name}.scale")))
+                .ok()
+                .and_then(|scale| scale.parse().ok())
+            else {
+                continue;
+            };
+
+            events.push(PerfEventSpec {
+                name: name.to_string(),
+                config,
+                scale,
+            });
+        }
+        events.sort_by(|a, b| a.name.cmp(&b.name));
+
+        if events.is_empty() || cpus.is_empty() {
+            return Err(MeasurementError::PerfNotAvailable);
+        }
+
+        Ok(Self {
+            pmu_type,
+            events,
+            cpus,
+        })
+    }
+
+    /// The counters to open, one per event and package, named after the
+    /// zones of the powercap interface
+    fn counters(&self) -> Vec<CounterSpec> {
+        let mut counters = Vec::new();
+        for event in &self.events {
+            let domain = match event.name.trim_start_matches("energy-") {
+                "cores" => "core",
+                "ram" => "dram",
+                "gpu" => "uncore",
+                domain => domain,
+            };
+            for (package, &cpu) in self.cpus.iter().enumerate() {
+                let name = match domain {
+                    "pkg" => format!("package-{package}"),
+                    // psys covers the whole platform rather than a package
+                    "psys" if package == 0 => "psys".to_string(),
+                    "psys" => continue,
+                    _ => format!("package-{package}/{domain}"),
+                };
+                counters.push(CounterSpec {
+                    name,
+                    config: event.config,
+                    cpu,
+                    scale: event.scale,
+                    counted: domain == "pkg" || domain == "dram",
+                });
+            }
+        }
+
+        // As with sysfs RAPL, psys only counts when nothing else does
+        if !counters.iter().any(|counter| counter.counted) {
+            for counter in counters.iter_mut().filter(|c| c.name == "psys") {
+                counter.counted = true;
+            }
+        }
+        counters
+    }
+}
+
+/// A counter to open for one event on one package
+#[derive(Debug, Clone, PartialEq)]
+struct CounterSpec {
+    /// Domain name, e.g. `package-0` or `package-0/dram`
+    name: String,
+    config: u64,
+    /// CPU of the package the counter is opened on
+    cpu: i32,
+    scale: f64,
+    /// Whether the counter contributes to the summed total
+    counted: bool,
+}
+
+/// Parses the `event=0x02` style description of a PMU event
+fn parse_event_config(description: &str) -> Option<u64> {
+    description.split(',').find_map(|term| {
+        let value = term.trim().strip_prefix("event=")?;
+        match value.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => value.parse().ok(),
+        }
+    })
+}
+
+/// Parses a CPU list such as `0,36` or `0-3,8`
+fn parse_cpu_list(list: &str) -> Option<Vec<i32>> {
+    let mut cpus = Vec::new();
+    for part in list.split(',').filter(|part| !part.is_empty()) {
+        match part.split_once('-') {
+            Some((start, end)) => cpus.extend(start.parse::<i32>().ok()?..=end.parse().ok()?),
+            None => cpus.push(part.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+/// An open perf counter for one event on one package
+struct PerfCounter {
+    /// Domain name, e.g. `package-0` or `package-0/dram`
+    name: String,
+    file: File,
+    scale: f64,
+    /// Whether the counter contributes to the summed total
+    counted: bool,
+    start: u64,
+    last: u64,
+}
+
+impl PerfCounter {
+    fn read(&mut self) -> io::Result<u64> {
+        let mut value = [0u8; 8];
+        self.file.read_exact(&mut value)?;
+        Ok(u64::from_ne_bytes(value))
+    }
+
+    fn energy(&self) -> Energy {
+        Energy::new::<joule>((self.last - self.start) as f64 * self.scale)
+    }
+}
+
+/// RAPL measurement through the `power` PMU of `perf_event_open`
+///
+/// This works on hosts where the powercap `energy_uj` files are root-only
+/// but `perf_event_paranoid` allows system-wide counters. perf extends the
+/// hardware counters to 64 bits, so no wraparound correction is needed.
+pub struct PerfRaplMeasurement {
+    counters: Vec<PerfCounter>,
+}
+
+impl PerfRaplMeasurement {
+    /// Creates a new perf RAPL measurement instance
+    pub fn new() -> Result<Self, MeasurementError> {
+        Self::with_root("/")
+    }
+
+    /// Creates a new perf RAPL measurement instance reading the PMU
+    /// description below `root` instead of `/`
+    pub fn with_root(root: impl AsRef<Path>) -> Result<Self, MeasurementError> {
+        let pmu = PowerPmu::discover(root.as_ref())?;
+
+        let mut counters = Vec::new();
+        for spec in pmu.counters() {
+            let file = perf_event_open(pmu.pmu_type, spec.config, spec.cpu)
+                .map_err(|_| MeasurementError::PerfNotAvailable)?;
+            let mut counter = PerfCounter {
+                name: spec.name,
+                file,
+                scale: spec.scale,
+                counted: spec.counted,
+                start: 0,
+                last: 0,
+            };
+            counter.start = counter.read()?;
+            counter.last = counter.start;
+            counters.push(counter);
+        }
+
+        Ok(Self { counters })
+    }
+
+    /// Names of the RAPL domains this instance reads, in reporting order
+    pub fn domains(&self) -> impl Iterator<Item = &str> {
+        self.counters.iter().map(|counter| counter.name.as_str())
+    }
+}
+
+impl PowerMeter for PerfRaplMeasurement {
+    fn source(&self) -> PowerSource {
+        PowerSource::PerfRapl
+    }
+
+    fn capabilities(&self) -> MeterCapabilities {
+        MeterCapabilities {
+            instantaneous_power: false,
+            cumulative_energy: true,
+            power_resolution: None,
+            energy_resolution: self
+                .counters
+                .iter()
+                .map(|counter| counter.scale)
+                .reduce(f64::min)
+                .map(Energy::new::<joule>),
+        }
+    }
+
+    fn read_energy(&mut self) -> Result<Energy, MeasurementError> {
+        let mut total = Energy::new::<joule>(0.0);
+        for counter in &mut self.counters {
+            counter.last = counter.read()?;
+            if counter.counted {
+                total += counter.energy();
+            }
+        }
+        Ok(total)
+    }
+
+    fn domains(&self) -> Vec<DomainReading> {
+        self.counters
+            .iter()
+            .map(|counter| DomainReading {
+                name: counter.name.clone(),
+                energy: counter.energy(),
+                included_in_total: counter.counted,
+            })
+            .collect()
+    }
+}
+
+/// The leading fields of `struct perf_event_attr`, up to
+/// `PERF_ATTR_SIZE_VER1`; the kernel treats the rest as zero
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+    config2: u64,
+}
+
+/// Opens a system-wide counting event on `cpu`
+#[cfg(target_os = "linux")]
+fn perf_event_open(pmu_type: u32, config: u64, cpu: i32) -> io::Result<File> {
+    use std::os::fd::FromRawFd;
+
+    const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+    let attr = PerfEventAttr {
+        type_: pmu_type,
+        size: std::mem::size_of::<PerfEventAttr>() as u32,
+        config,
+        ..Default::default()
+    };
+    // SAFETY: attr is a valid perf_event_attr prefix whose size field
+    // matches, and pid -1 with a cpu opens a system-wide counter
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_perf_event_open,
+            &attr as *const PerfEventAttr,
+            -1 as libc::pid_t,
+            cpu as libc::c_int,
+            -1 as libc::c_int,
+            PERF_FLAG_FD_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the kernel returned a fresh descriptor that nothing else owns
+    Ok(unsafe { File::from_raw_fd(fd as libc::c_int) })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn perf_event_open(_pmu_type: u32, _config: u64, _cpu: i32) -> io::Result<File> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::FakeRoot;
+
+    #[test]
+    fn test_parse_event_config() {
+        assert_eq!(parse_event_config("event=0x02\n"), Some(2));
+        assert_eq!(parse_event_config("event=0x1b,umask=0x0"), Some(0x1b));
+        assert_eq!(parse_event_config("event=3"), Some(3));
+        assert_eq!(parse_event_config("umask=0x01"), None);
+    }
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("0"), Some(vec![0]));
+        assert_eq!(parse_cpu_list("0,36"), Some(vec![0, 36]));
+        assert_eq!(parse_cpu_list("0-2,8"), Some(vec![0, 1, 2, 8]));
+        assert_eq!(parse_cpu_list("zero"), None);
+    }
+
+    const BASE: &str = "sys/bus/event_source/devices/power";
+
+    /// Describes a `power` PMU with `events` of a name, config and scale
+    fn power_pmu(name: &str, cpumask: &str, events: &[(&str, u64, &str)]) -> FakeRoot {
+        let root = FakeRoot::new(name);
+        root.file(&format!("{BASE}/type"), "24\n")
+            .file(&format!("{BASE}/cpumask"), &format!("{cpumask}\n"));
+        for (name, event, scale) in events {
+            root.file(
+                &format!("{BASE}/events/{name}"),
+                &format!("event=0x{event:02x}\n"),
+            )
+            .file(
+                &format!("{BASE}/events/{name}.scale"),
+                &format!("{scale}\n"),
+            )
+            .file(&format!("{BASE}/events/{name}.unit"), "Joules\n");
+        }
+        root
+    }
+
+    /// Name, CPU, scale and whether it counts of each counter to open
+    fn counters(root: &FakeRoot) -> Vec<(String, i32, f64, bool)> {
+        PowerPmu::discover(root.path())
+            .unwrap()
+            .counters()
+            .into_iter()
+            .map(|spec| (spec.name, spec.cpu, spec.scale, spec.counted))
+            .collect()
+    }
+
+    #[test]
+    fn test_discover_power_pmu() {
+        let scale = "2.3283064365386962890625e-10";
+        let root = power_pmu(
+            "perf-power",
+            "0,36",
+            &[
+                ("energy-pkg", 2, scale),
+                ("energy-ram", 3, scale),
+                ("energy-cores", 1, scale),
+            ],
+        );
+        // An event without a unit cannot be scaled and is ignored
+        root.file(&format!("{BASE}/events/energy-gpu"), "event=0x04\n");
+
+        let pmu = PowerPmu::discover(root.path()).unwrap();
+        assert_eq!(pmu.pmu_type, 24);
+        assert_eq!(pmu.cpus, [0, 36]);
+        assert_eq!(
+            pmu.events
+                .iter()
+                .map(|event| (event.name.as_str(), event.config))
+                .collect::<Vec<_>>(),
+            [("energy-cores", 1), ("energy-pkg", 2), ("energy-ram", 3)]
+        );
+        assert!((pmu.events[0].scale - 2.3283064365e-10).abs() < 1e-20);
+    }
+
+    #[test]
+    fn test_counters_package_and_psys() {
+        let scale = "2.3283064365386962890625e-10";
+        let root = power_pmu(
+            "perf-psys",
+            "0,36",
+            &[("energy-pkg", 2, scale), ("energy-psys", 5, scale)],
+        );
+        let scale = 2f64.powi(-32);
+        assert_eq!(
+            counters(&root),
+            [
+                ("package-0".to_string(), 0, scale, true),
+                ("package-1".to_string(), 36, scale, true),
+                // Once, and only counted when nothing else is
+                ("psys".to_string(), 0, scale, false),
+            ]
+        );
+
+        let root = power_pmu("perf-psys-only", "0", &[("energy-psys", 5, "0.5")]);
+        assert_eq!(counters(&root), [("psys".to_string(), 0, 0.5, true)]);
+    }
+
+    #[test]
+    fn test_counters_package_and_cores() {
+        // A scale of 2^-14 J, unlike the usual 2^-32 J
+        let root = power_pmu(
+            "perf-cores",
+            "0",
+            &[
+                ("energy-pkg", 2, "6.103515625e-05"),
+                ("energy-cores", 1, "6.103515625e-05"),
+            ],
+        );
+        // Cores are part of the package, so they do not add to the total
+        assert_eq!(
+            counters(&root),
+            [
+                ("package-0/core".to_string(), 0, 6.103515625e-05, false),
+                ("package-0".to_string(), 0, 6.103515625e-05, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_power_pmu_missing() {
+        let root = FakeRoot::new("perf-none");
+        assert!(matches!(
+            PerfRaplMeasurement::with_root(root.path()),
+            Err(MeasurementError::PerfNotAvailable)
+        ));
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use uom::si::f64::{Energy, Power};
+use uom::si::{energy::joule, power::watt};
+
+use crate::PowerSample;
+
+/// Energy of the part of a measurement between two markers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Phase {
+    /// Name the phase was marked with
+    pub name: String,
+    /// Time from the start of the measurement to the marker
+    pub start: Duration,
+    /// Time until the next marker or the end of the measurement
+    pub duration: Duration,
+    /// Energy consumed during the phase
+    pub energy: Energy,
+    /// Average power during the phase
+    pub average_power: Power,
+}
+
+/// Named markers dropped into a measurement in progress
+///
+/// Each marker starts a [`Phase`] that lasts until the next marker or the
+/// end of the measurement; time before the first marker is not part of any
+/// phase. Clones share their markers, so they can be handed to other
+/// threads or tasks of the workload.
+#[derive(Debug, Clone, Default)]
+pub struct PhaseMarkers {
+    markers: Arc<Mutex<Vec<(String, Instant)>>>,
+}
+
+impl PhaseMarkers {
+    /// Ends the current phase and starts one called `name`
+    pub fn mark(&self, name: impl Into<String>) {
+        let now = Instant::now();
+        self.markers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((name.into(), now));
+    }
+
+    /// Splits the readings of a measurement that started at `start` and
+    /// lasted `duration` into phases
+    ///
+    /// Phase boundaries fall between readings, so the energy of a phase is
+    /// interpolated from the readings around it: linearly for meters that
+    /// only report power, and at the interval's average power otherwise.
+    pub(crate) fn phases(
+        &self,
+        start: Instant,
+        duration: Duration,
+        samples: &[PowerSample],
+        power_only: bool,
+    ) -> Vec<Phase> {
+        let mut markers: Vec<(String, Duration)> = self
+            .markers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(name, time)| {
+                (
+                    name.clone(),
+                    time.saturating_duration_since(start).min(duration),
+                )
+            })
+            .collect();
+        // Markers from several threads may arrive out of order
+        markers.sort_by_key(|(_, offset)| *offset);
+
+        let ends: Vec<Duration> = markers
+            .iter()
+            .skip(1)
+            .map(|(_, offset)| *offset)
+            .chain([duration])
+            .collect();
+        markers
+            .into_iter()
+            .zip(ends)
+            .map(|((name, from), to)| {
+                let energy = Energy::new::<joule>(window_joules(samples, from, to, power_only));
+                let seconds = (to - from).as_secs_f64();
+                Phase {
+                    name,
+                    start: from,
+                    duration: to - from,
+                    energy,
+                    average_power: if seconds > 0.0 {
+                        Power::new::<watt>(energy.get::<joule>() / seconds)
+                    } else {
+                        Power::default()
+                    },
+                }
+            })
+            .collect()
+    }
+}
+
+/// Energy in joules between the offsets `from` and `to`
+pub(crate) fn window_joules(
+    samples: &[PowerSample],
+    from: Duration,
+    to: Duration,
+    power_only: bool,
+) -> f64 {
+    let from = from.as_secs_f64();
+    let to = to.as_secs_f64();
+    let mut joules = 0.0;
+    let mut previous: Option<&PowerSample> = None;
+    for sample in samples {
+        let end = sample.offset.as_secs_f64();
+        let begin = previous.map_or(0.0, |previous| previous.offset.as_secs_f64());
+        let (low, high) = (begin.max(from), end.min(to));
+        if high > low {
+            let watts = sample.power.get::<watt>();
+            joules += match previous {
+                // Power readings are interpolated between their times
+                Some(previous) if power_only => {
+                    let first = previous.power.get::<watt>();
+                    let at = |t: f64| first + (watts - first) * (t - begin) / (end - begin);
+                    (at(low) + at(high)) / 2.0 * (high - low)
+                }
+                // Energy readings cover the interval since the previous one
+                _ => watts * (high - low),
+            };
+        }
+        previous = Some(sample);
+    }
+    joules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(millis: u64, watts: f64) -> PowerSample {
+        PowerSample {
+            offset: Duration::from_millis(millis),
+            power: Power::new::<watt>(watts),
+            domains: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_phases() {
+        let start = Instant::now();
+        let markers = PhaseMarkers::default();
+        // Recorded out of order, as from two threads
+        markers.markers.lock().unwrap().extend([
+            ("test".to_string(), start + Duration::from_millis(300)),
+            ("build".to_string(), start + Duration::from_millis(100)),
+        ]);
+
+        // 10 W for the first 200 ms, then 30 W
+        let samples = [sample(100, 10.0), sample(200, 10.0), sample(400, 30.0)];
+        let phases = markers.phases(start, Duration::from_millis(400), &samples, false);
+        let summary: Vec<(&str, u128, f64)> = phases
+            .iter()
+            .map(|phase| {
+                (
+                    phase.name.as_str(),
+                    phase.duration.as_millis(),
+                    phase.energy.get::<joule>(),
+                )
+            })
+            .collect();
+        assert_eq!(summary[0].0, "build");
+        assert_eq!(summary[0].1, 200);
+        assert!((summary[0].2 - 4.0).abs() < 1e-9);
+        assert_eq!(summary[1].0, "test");
+        assert_eq!(summary[1].1, 100);
+        assert!((summary[1].2 - 3.0).abs() < 1e-9);
+        assert!((phases[1].average_power.get::<watt>() - 30.0).abs() < 1e-9);
+
+        // A ramp from 0 W to 40 W over 400 ms
+        let samples = [sample(0, 0.0), sample(400, 40.0)];
+        let phases = markers.phases(start, Duration::from_millis(400), &samples, true);
+        // Between 10 W and 30 W, and between 30 W and 40 W
+        assert!((phases[0].energy.get::<joule>() - 4.0).abs() < 1e-9);
+        assert!((phases[1].energy.get::<joule>() - 3.5).abs() < 1e-9);
+    }
+}
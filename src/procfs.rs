@@ -0,0 +1,238 @@
+use std::{cell::OnceCell, collections::HashMap, fs, path::Path};
+
+use crate::MeasurementError;
+
+/// System-wide CPU time from the `cpu` line of `/proc/stat`, in clock ticks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct SystemCpuTimes {
+    /// Time spent running anything, i.e. everything except idle and iowait
+    pub(crate) busy: u64,
+    /// Busy plus idle time
+    pub(crate) total: u64,
+    /// Number of `cpuN` lines
+    pub(crate) cpus: usize,
+}
+
+/// Reads `proc/stat` below `root`
+pub(crate) fn read_system_cpu_times(root: &Path) -> Result<SystemCpuTimes, MeasurementError> {
+    let stat = fs::read_to_string(root.join("proc/stat"))?;
+
+    let mut times = None;
+    let mut cpus = 0;
+    for line in stat.lines() {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("cpu") => {
+                let values: Vec<u64> = fields.filter_map(|value| value.parse().ok()).collect();
+                // user nice system idle iowait irq softirq steal; guest time
+                // is already part of user and nice
+                if values.len() < 4 {
+                    break;
+                }
+                let idle = values[3] + values.get(4).copied().unwrap_or(0);
+                let busy = values.iter().take(8).sum::<u64>() - idle;
+                times = Some((busy, busy + idle));
+            }
+            Some(cpu) if cpu.starts_with("cpu") => cpus += 1,
+            _ => {}
+        }
+    }
+
+    let (busy, total) = times.ok_or_else(|| {
+        MeasurementError::InvalidMeasurement("no cpu line in /proc/stat".to_string())
+    })?;
+    Ok(SystemCpuTimes {
+        busy,
+        total,
+        cpus: cpus.max(1),
+    })
+}
+
+/// The fields of `/proc/<pid>/stat` used for CPU accounting
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ProcessStat {
+    pub(crate) ppid: u32,
+    /// utime + stime in clock ticks
+    pub(crate) cpu_time: u64,
+    /// cutime + cstime of waited-for children in clock ticks
+    pub(crate) children_cpu_time: u64,
+}
+
+/// Reads `proc/<pid>/stat` below `root`, where `pid` may also be `self`
+pub(crate) fn read_process_stat(root: &Path, pid: &str) -> Result<ProcessStat, MeasurementError> {
+    let stat = fs::read_to_string(root.join("proc").join(pid).join("stat"))?;
+    parse_process_stat(&stat).ok_or_else(|| {
+        MeasurementError::InvalidMeasurement(format!("Failed to parse /proc/{}/stat", pid))
+    })
+}
+
+/// Reads the stat of every process in `proc` below `root`
+pub(crate) fn read_processes(root: &Path) -> Result<HashMap<u32, ProcessStat>, MeasurementError> {
+    let mut processes = HashMap::new();
+    for entry in fs::read_dir(root.join("proc"))?.flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|n| n.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        // Processes may exit while the directory is being read
+        if let Ok(stat) = read_process_stat(root, &pid.to_string()) {
+            processes.insert(pid, stat);
+        }
+    }
+    Ok(processes)
+}
+
+/// The processes in `proc`, read once on first use and shared by the
+/// probes of a sample, which all read below the same root
+#[derive(Debug, Default)]
+pub(crate) struct ProcessScan {
+    processes: OnceCell<Option<HashMap<u32, ProcessStat>>>,
+}
+
+impl ProcessScan {
+    /// The stat of every process in `proc` below `root`
+    pub(crate) fn processes(
+        &self,
+        root: &Path,
+    ) -> Result<&HashMap<u32, ProcessStat>, MeasurementError> {
+        self.processes
+            .get_or_init(|| read_processes(root).ok())
+            .as_ref()
+            .ok_or_else(|| {
+                MeasurementError::InvalidMeasurement(format!(
+                    "Failed to read {}",
+                    root.join("proc").display()
+                ))
+            })
+    }
+}
+
+/// Extends `members` with all of their descendants in `processes`
+pub(crate) fn collect_descendants(processes: &HashMap<u32, ProcessStat>, members: &mut Vec<u32>) {
+    let mut index = 0;
+    while index < members.len() {
+        let parent = members[index];
+        for (pid, process) in processes {
+            if process.ppid == parent && !members.contains(pid) {
+                members.push(*pid);
+            }
+        }
+        index += 1;
+    }
+}
+
+/// Reads the resident set size in bytes from `proc/<pid>/status` below
+/// `root`; kernel threads have none
+pub(crate) fn read_process_rss(root: &Path, pid: u32) -> Result<u64, MeasurementError> {
+    let status = fs::read_to_string(root.join("proc").join(pid.to_string()).join("status"))?;
+    Ok(status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|rss| rss.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        .map_or(0, |kilobytes| kilobytes * 1024))
+}
+
+fn parse_process_stat(stat: &str) -> Option<ProcessStat> {
+    // The command name may contain spaces and parentheses, so the fields
+    // are counted from the last closing parenthesis, starting at `state`
+    let (_, fields) = stat.rsplit_once(')')?;
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    let field = |number: usize| -> Option<u64> { fields.get(number - 3)?.parse().ok() };
+
+    Some(ProcessStat {
+        ppid: field(4)? as u32,
+        cpu_time: field(14)? + field(15)?,
+        children_cpu_time: field(16)? + field(17)?,
+    })
+}
+
+/// Clock ticks per second used by `/proc` CPU times
+pub(crate) fn clock_ticks_per_second() -> f64 {
+    // SAFETY: sysconf has no preconditions
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks > 0 {
+        ticks as f64
+    } else {
+        100.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::FakeRoot;
+
+    #[test]
+    fn test_read_system_cpu_times() {
+        let root = FakeRoot::new("proc-stat");
+        root.file(
+            "proc/stat",
+            "cpu  100 20 30 800 50 0 0 0 0 0\n\
+             cpu0 50 10 15 400 25 0 0 0 0 0\n\
+             cpu1 50 10 15 400 25 0 0 0 0 0\n\
+             intr 12345\n",
+        );
+
+        let times = read_system_cpu_times(root.path()).unwrap();
+        assert_eq!(
+            times,
+            SystemCpuTimes {
+                busy: 150,
+                total: 1000,
+                cpus: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_read_process_rss() {
+        let root = FakeRoot::new("proc-status");
+        root.file(
+            "proc/42/status",
+            "Name:\tcargo\nVmPeak:\t  300000 kB\nVmRSS:\t  204800 kB\nThreads:\t4\n",
+        )
+        .file("proc/2/status", "Name:\tkthreadd\nThreads:\t1\n");
+
+        assert_eq!(
+            read_process_rss(root.path(), 42).unwrap(),
+            200 * 1024 * 1024
+        );
+        assert_eq!(read_process_rss(root.path(), 2).unwrap(), 0);
+        assert!(read_process_rss(root.path(), 7).is_err());
+    }
+
+    #[test]
+    fn test_process_scan() {
+        let root = FakeRoot::new("proc-scan");
+        let stat = |pid: u32| format!("{pid} (sh) S 1 1 1 0 -1 0 0 0 0 0 0 0 0 0 20 0 1 0 1 0 0");
+        root.file("proc/100/stat", &stat(100));
+
+        let scan = ProcessScan::default();
+        assert_eq!(scan.processes(root.path()).unwrap().len(), 1);
+        // Processes started after the scan belong to the next one
+        root.file("proc/200/stat", &stat(200));
+        assert_eq!(scan.processes(root.path()).unwrap().len(), 1);
+        assert_eq!(
+            ProcessScan::default().processes(root.path()).unwrap().len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_parse_process_stat() {
+        let stat = "4242 (tricky) name) S 4200 4242 4200 0 -1 4194304 80 0 0 0 \
+                    120 30 7 3 20 0 1 0 139115 2703360 305";
+        assert_eq!(
+            parse_process_stat(stat),
+            Some(ProcessStat {
+                ppid: 4200,
+                cpu_time: 150,
+                children_cpu_time: 10,
+            })
+        );
+        assert_eq!(parse_process_stat("4242 (truncated) S 1"), None);
+    }
+}
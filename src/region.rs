@@ -0,0 +1,397 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use uom::si::energy::joule;
+use uom::si::f64::Energy;
+
+use crate::phase::window_joules;
+use crate::PowerSample;
+
+/// Energy of a named region and the regions nested in it
+///
+/// Regions with the same name and parent are merged, so a region entered
+/// in a loop is reported once with the number of times it was entered.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegionEnergy {
+    /// Name the region was entered with
+    pub name: String,
+    /// Number of times the region was entered
+    pub calls: usize,
+    /// Total time spent in the region
+    pub duration: Duration,
+    /// Energy consumed in the region, including its nested regions
+    pub inclusive_energy: Energy,
+    /// Energy consumed in the region outside its nested regions
+    pub exclusive_energy: Energy,
+    /// Regions entered from this one, in the order first entered
+    pub children: Vec<RegionEnergy>,
+}
+
+/// Regions entered with the same name from the same parent, merged as
+/// they are entered
+#[derive(Debug)]
+struct RegionNode {
+    name: String,
+    /// Nodes entered from this one, in the order first entered
+    children: Vec<usize>,
+    calls: usize,
+    duration: Duration,
+    joules: f64,
+}
+
+/// An entry into a region whose energy is not known yet
+#[derive(Debug)]
+struct Entry {
+    node: usize,
+    start: Instant,
+    /// Unset while the region is open
+    end: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+struct RegionState {
+    nodes: Vec<RegionNode>,
+    /// Node of each name under each parent, `None` for top-level regions
+    index: HashMap<(Option<usize>, String), usize>,
+    /// Top-level nodes in the order first entered
+    roots: Vec<usize>,
+    /// Entries still open or left after the last reading, by id
+    entries: HashMap<u64, Entry>,
+    next_entry: u64,
+}
+
+impl RegionState {
+    /// Adds the energy of `entry`, which ends at `to`, to its node
+    fn settle(
+        &mut self,
+        entry: &Entry,
+        to: Duration,
+        start: Instant,
+        samples: &[PowerSample],
+        power_only: bool,
+    ) {
+        let from = entry.start.saturating_duration_since(start).min(to);
+        // Only the readings around the entry are needed
+        let first = samples
+            .partition_point(|sample| sample.offset < from)
+            .saturating_sub(1);
+        let node = &mut self.nodes[entry.node];
+        node.duration += to - from;
+        node.joules += window_joules(&samples[first..], from, to, power_only);
+    }
+
+    fn energy(&self, node: usize) -> RegionEnergy {
+        let node = &self.nodes[node];
+        let children: Vec<RegionEnergy> = node
+            .children
+            .iter()
+            .map(|&child| self.energy(child))
+            .collect();
+        let children_joules: f64 = children
+            .iter()
+            .map(|child| child.inclusive_energy.get::<joule>())
+            .sum();
+        RegionEnergy {
+            name: node.name.clone(),
+            calls: node.calls,
+            duration: node.duration,
+            inclusive_energy: Energy::new::<joule>(node.joules),
+            // Children running concurrently can add up to more than their
+            // parent
+            exclusive_energy: Energy::new::<joule>((node.joules - children_joules).max(0.0)),
+            children,
+        }
+    }
+}
+
+/// Nestable named regions of a measurement in progress
+///
+/// Clones share their regions, so they can be handed to other threads of
+/// the workload. Entries into the same region are merged as they happen
+/// and their energy is added up as readings come in, so a region entered
+/// in a hot loop takes no more memory than one entered once.
+#[derive(Debug, Clone, Default)]
+pub struct Regions {
+    state: Arc<Mutex<RegionState>>,
+}
+
+impl Regions {
+    /// Enters a top-level region called `name`, left when the returned
+    /// [`Region`] is dropped
+    pub fn region(&self, name: impl Into<String>) -> Region {
+        self.enter(name.into(), None, Instant::now())
+    }
+
+    fn enter(&self, name: String, parent: Option<usize>, start: Instant) -> Region {
+        let mut state = self.lock();
+        let state = &mut *state;
+        let key = (parent, name);
+        let node = match state.index.get(&key) {
+            Some(&node) => node,
+            None => {
+                let node = state.nodes.len();
+                state.nodes.push(RegionNode {
+                    name: key.1.clone(),
+                    children: Vec::new(),
+                    calls: 0,
+                    duration: Duration::ZERO,
+                    joules: 0.0,
+                });
+                match parent {
+                    Some(parent) => state.nodes[parent].children.push(node),
+                    None => state.roots.push(node),
+                }
+                state.index.insert(key, node);
+                node
+            }
+        };
+        state.nodes[node].calls += 1;
+
+        let entry = state.next_entry;
+        state.next_entry += 1;
+        state.entries.insert(
+            entry,
+            Entry {
+                node,
+                start,
+                end: None,
+            },
+        );
+        Region {
+            regions: self.clone(),
+            node,
+            entry,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, RegionState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Adds up the energy of the entries that ended before the last of
+    /// `samples`, of a measurement that started at `start`
+    pub(crate) fn settle(&self, start: Instant, samples: &[PowerSample], power_only: bool) {
+        let Some(last) = samples.last() else {
+            return;
+        };
+        let mut state = self.lock();
+        let settled: Vec<u64> = state
+            .entries
+            .iter()
+            .filter(|(_, entry)| {
+                entry
+                    .end
+                    .is_some_and(|end| end.saturating_duration_since(start) <= last.offset)
+            })
+            .map(|(&id, _)| id)
+            .collect();
+        for id in settled {
+            let entry = state.entries.remove(&id).expect("settled entries exist");
+            let to = entry
+                .end
+                .map_or(last.offset, |end| end.saturating_duration_since(start));
+            state.settle(&entry, to, start, samples, power_only);
+        }
+    }
+
+    /// Builds the region tree of a measurement that started at `start` and
+    /// lasted `duration`
+    ///
+    /// The energy of each region is interpolated from the readings as for
+    /// [`PhaseMarkers`](crate::PhaseMarkers); regions still open at the end
+    /// of the measurement end with it.
+    pub(crate) fn tree(
+        &self,
+        start: Instant,
+        duration: Duration,
+        samples: &[PowerSample],
+        power_only: bool,
+    ) -> Vec<RegionEnergy> {
+        let mut state = self.lock();
+        let entries: Vec<Entry> = state.entries.drain().map(|(_, entry)| entry).collect();
+        for entry in &entries {
+            let offset = |time: Instant| time.saturating_duration_since(start).min(duration);
+            let to = entry.end.map_or(duration, offset);
+            state.settle(entry, to, start, samples, power_only);
+        }
+        state.roots.iter().map(|&root| state.energy(root)).collect()
+    }
+}
+
+/// A region being measured, left when dropped
+#[must_use = "the region is left as soon as it is dropped"]
+#[derive(Debug)]
+pub struct Region {
+    regions: Regions,
+    node: usize,
+    entry: u64,
+}
+
+impl Region {
+    /// Enters a region called `name` nested in this one
+    pub fn region(&self, name: impl Into<String>) -> Region {
+        self.regions
+            .enter(name.into(), Some(self.node), Instant::now())
+    }
+}
+
+impl Drop for Region {
+    fn drop(&mut self) {
+        let now = Instant::now();
+        // Regions still open when the measurement ended are already done
+        if let Some(entry) = self.regions.lock().entries.get_mut(&self.entry) {
+            entry.end.get_or_insert(now);
+        }
+    }
+}
+
+/// The regions of a measurement as an indented table, from
+/// [`EnergyMeasurement::region_report`](crate::EnergyMeasurement::region_report)
+pub struct RegionReport<'a> {
+    pub(crate) regions: &'a [RegionEnergy],
+    pub(crate) total_energy: Energy,
+}
+
+impl RegionReport<'_> {
+    fn write_rows(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        regions: &[RegionEnergy],
+        depth: usize,
+        width: usize,
+    ) -> std::fmt::Result {
+        let total = self.total_energy.get::<joule>();
+        for region in regions {
+            let inclusive = region.inclusive_energy.get::<joule>();
+            let share = if total > 0.0 {
+                inclusive / total * 100.0
+            } else {
+                0.0
+            };
+            write!(
+                f,
+                "\n{:<width$}  {:>6}  {:>13.3}  {:>13.3}  {:>6.1}%",
+                format!("{:indent$}{}", "", region.name, indent = depth * 2),
+                region.calls,
+                inclusive,
+                region.exclusive_energy.get::<joule>(),
+                share,
+            )?;
+            self.write_rows(f, &region.children, depth + 1, width)?;
+        }
+        Ok(())
+    }
+}
+
+/// Widest indented region name in `regions`
+fn name_width(regions: &[RegionEnergy], depth: usize) -> usize {
+    regions
+        .iter()
+        .map(|region| {
+            (depth * 2 + region.name.chars().count()).max(name_width(&region.children, depth + 1))
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+impl Display for RegionReport<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let width = name_width(self.regions, 0).max("Region".len());
+        write!(
+            f,
+            "{:<width$}  {:>6}  {:>13}  {:>13}  {:>7}",
+            "Region", "Calls", "Inclusive (J)", "Exclusive (J)", "Share"
+        )?;
+        self.write_rows(f, self.regions, 0, width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uom::si::{f64::Power, power::watt};
+
+    /// Enters `name` at `from` ms after `start` and leaves it at `to` ms,
+    /// returning its node
+    fn enter(
+        regions: &Regions,
+        name: &str,
+        parent: Option<usize>,
+        start: Instant,
+        (from, to): (u64, u64),
+    ) -> usize {
+        let region = regions.enter(
+            name.to_string(),
+            parent,
+            start + Duration::from_millis(from),
+        );
+        let end = start + Duration::from_millis(to);
+        regions.lock().entries.get_mut(&region.entry).unwrap().end = Some(end);
+        region.node
+    }
+
+    #[test]
+    fn test_region_tree() {
+        let start = Instant::now();
+        let regions = Regions::default();
+        let request = enter(&regions, "request", None, start, (0, 400));
+        enter(&regions, "db", Some(request), start, (0, 100));
+        enter(&regions, "render", Some(request), start, (100, 200));
+        enter(&regions, "db", Some(request), start, (200, 300));
+        let request = enter(&regions, "request", None, start, (400, 1000));
+        enter(&regions, "db", Some(request), start, (400, 500));
+        // Still open when the measurement ends
+        let flush = regions.enter(
+            "flush".to_string(),
+            None,
+            start + Duration::from_millis(900),
+        );
+        assert_eq!(regions.lock().nodes.len(), 4);
+
+        // 10 W throughout, counted once per 100 ms
+        let samples: Vec<PowerSample> = (1..=10)
+            .map(|i| PowerSample {
+                offset: Duration::from_millis(i * 100),
+                power: Power::new::<watt>(10.0),
+                domains: Vec::new(),
+            })
+            .collect();
+        // Entries that ended before a reading are added up right away
+        regions.settle(start, &samples[..5], false);
+        assert_eq!(regions.lock().entries.len(), 2);
+        let tree = regions.tree(start, Duration::from_millis(1000), &samples, false);
+        assert!(regions.lock().entries.is_empty());
+        drop(flush);
+
+        let names: Vec<&str> = tree.iter().map(|region| region.name.as_str()).collect();
+        assert_eq!(names, ["request", "flush"]);
+        let request = &tree[0];
+        assert_eq!(request.calls, 2);
+        assert_eq!(request.duration, Duration::from_millis(1000));
+        assert!((request.inclusive_energy.get::<joule>() - 10.0).abs() < 1e-9);
+        assert!((request.exclusive_energy.get::<joule>() - 6.0).abs() < 1e-9);
+        let db = &request.children[0];
+        assert_eq!((db.name.as_str(), db.calls), ("db", 3));
+        assert!((db.inclusive_energy.get::<joule>() - 3.0).abs() < 1e-9);
+        assert_eq!(db.inclusive_energy, db.exclusive_energy);
+        assert_eq!(request.children[1].name, "render");
+        assert!((tree[1].inclusive_energy.get::<joule>() - 1.0).abs() < 1e-9);
+
+        let report = RegionReport {
+            regions: &tree,
+            total_energy: Energy::new::<joule>(20.0),
+        }
+        .to_string();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("Region "));
+        assert!(lines[2].starts_with("  db "));
+        assert!(lines[2].ends_with("15.0%"));
+        assert!(lines[1].contains("10.000") && lines[1].contains("6.000"));
+    }
+}
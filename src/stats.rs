@@ -0,0 +1,480 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use uom::si::{energy::joule, power::watt};
+
+use crate::{EnergyMeasurement, MeasurementError};
+
+/// Two-sided 95% critical values of Student's t distribution for 1 to 30
+/// degrees of freedom
+const T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+
+/// Modified z-score above which a value is an outlier (Iglewicz and Hoaglin)
+const OUTLIER_Z_SCORE: f64 = 3.5;
+
+/// p-value below which a [`WelchTest`] difference is significant
+pub const SIGNIFICANCE_LEVEL: f64 = 0.05;
+
+/// Descriptive statistics of a series of runs
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct Summary {
+    /// Arithmetic mean
+    pub mean: f64,
+    /// Median
+    pub median: f64,
+    /// Sample standard deviation, zero for a single run
+    pub std_dev: f64,
+    /// Smallest value
+    pub min: f64,
+    /// Largest value
+    pub max: f64,
+    /// Lower bound of the 95% confidence interval of the mean
+    pub ci_low: f64,
+    /// Upper bound of the 95% confidence interval of the mean
+    pub ci_high: f64,
+}
+
+impl Summary {
+    /// Summarizes `values`, or returns `None` if there are none
+    pub fn new(values: &[f64]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let std_dev = if values.len() > 1 {
+            (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt()
+        } else {
+            0.0
+        };
+        let margin = t_critical(values.len() - 1) * std_dev / n.sqrt();
+        Some(Self {
+            mean,
+            median: median(values),
+            std_dev,
+            min: values.iter().copied().fold(f64::INFINITY, f64::min),
+            max: values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            ci_low: mean - margin,
+            ci_high: mean + margin,
+        })
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.3} ± {:.3} (95% CI {:.3} … {:.3}, median {:.3}, range {:.3} … {:.3})",
+            self.mean, self.std_dev, self.ci_low, self.ci_high, self.median, self.min, self.max
+        )
+    }
+}
+
+/// The runs of [`BenchmarkExecutor::measure_repeated`](crate::BenchmarkExecutor::measure_repeated)
+/// and their statistics
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RepeatedMeasurement {
+    /// The measured runs, excluding warmup runs
+    pub runs: Vec<EnergyMeasurement>,
+    /// Number of warmup runs that were discarded
+    pub warmup: usize,
+    /// Total energy per run in joules
+    pub energy: Summary,
+    /// Average power per run in watts
+    pub average_power: Summary,
+    /// Duration per run in seconds
+    pub duration: Summary,
+    /// Idle baseline power per run in watts, when every run measured one
+    #[serde(default)]
+    pub baseline_power: Option<Summary>,
+    /// Energy above the idle baseline per run in joules, when every run
+    /// measured one
+    #[serde(default)]
+    pub net_energy: Option<Summary>,
+    /// Energy attributed to the workload per run in joules
+    #[serde(default)]
+    pub attributed_energy: Option<Summary>,
+    /// Estimated memory energy per run in joules
+    #[serde(default)]
+    pub memory_energy: Option<Summary>,
+    /// Estimated network transfer energy per run in joules
+    #[serde(default)]
+    pub network_energy: Option<Summary>,
+    /// Estimated storage I/O energy per run in joules
+    #[serde(default)]
+    pub storage_energy: Option<Summary>,
+    /// Indices of the runs whose energy, net of the baseline when there is
+    /// one, is a statistical outlier
+    pub outliers: Vec<usize>,
+}
+
+impl RepeatedMeasurement {
+    /// Summarizes `runs`, of which there must be at least one
+    pub fn new(runs: Vec<EnergyMeasurement>, warmup: usize) -> Result<Self, MeasurementError> {
+        let series = |value: fn(&EnergyMeasurement) -> f64| -> Result<(Vec<f64>, Summary), MeasurementError> {
+            let values: Vec<f64> = runs.iter().map(value).collect();
+            let summary = Summary::new(&values).ok_or_else(|| {
+                MeasurementError::InvalidMeasurement("no runs to summarize".to_string())
+            })?;
+            Ok((values, summary))
+        };
+        // Figures only some runs have are left out
+        let optional = |value: fn(&EnergyMeasurement) -> Option<f64>| {
+            let values: Option<Vec<f64>> = runs.iter().map(value).collect();
+            Summary::new(&values?)
+        };
+        let (_, energy) = series(energy_joules)?;
+        let (_, average_power) = series(|run| run.average_power.get::<watt>())?;
+        let (_, duration) = series(|run| run.duration.as_secs_f64())?;
+        let baseline_power = optional(|run| Some(run.baseline.as_ref()?.power.get::<watt>()));
+        let net_energy = optional(|run| Some(run.baseline.as_ref()?.net_energy.get::<joule>()));
+        let attributed_energy =
+            optional(|run| Some(run.attribution.as_ref()?.attributed_energy.get::<joule>()));
+        let memory_energy = optional(|run| Some(run.memory.as_ref()?.energy.get::<joule>()));
+        let network_energy = optional(|run| Some(run.network.as_ref()?.energy.get::<joule>()));
+        let storage_energy = optional(|run| Some(run.storage.as_ref()?.energy.get::<joule>()));
+        let mut repeated = Self {
+            outliers: Vec::new(),
+            runs,
+            warmup,
+            energy,
+            average_power,
+            duration,
+            baseline_power,
+            net_energy,
+            attributed_energy,
+            memory_energy,
+            network_energy,
+            storage_energy,
+        };
+        repeated.outliers = outliers(&repeated.workload_energies());
+        Ok(repeated)
+    }
+
+    /// Statistics of the energy the runs are judged by: net of the idle
+    /// baseline when every run measured one, gross otherwise
+    pub fn workload_energy(&self) -> &Summary {
+        self.compared_energy(true)
+    }
+
+    /// Statistics of the net energy if `net` is set and every run has one,
+    /// of the gross energy otherwise
+    fn compared_energy(&self, net: bool) -> &Summary {
+        self.net_energy
+            .as_ref()
+            .filter(|_| net)
+            .unwrap_or(&self.energy)
+    }
+
+    /// Energy of each run that [`RepeatedMeasurement::compared_energy`]
+    /// summarizes
+    fn energies(&self, net: bool) -> Vec<f64> {
+        match self.net_energy {
+            Some(_) if net => self
+                .runs
+                .iter()
+                .map(|run| run.net_energy().get::<joule>())
+                .collect(),
+            _ => self.runs.iter().map(energy_joules).collect(),
+        }
+    }
+
+    /// Energy of each run that [`RepeatedMeasurement::workload_energy`]
+    /// summarizes
+    fn workload_energies(&self) -> Vec<f64> {
+        self.energies(true)
+    }
+}
+
+/// Welch's unequal variances t-test of the difference between two means
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct WelchTest {
+    /// The t statistic, negative when the first mean is smaller
+    pub t: f64,
+    /// Welch–Satterthwaite degrees of freedom
+    pub degrees_of_freedom: f64,
+    /// Two-sided p-value
+    pub p_value: f64,
+    /// Whether the p-value is below [`SIGNIFICANCE_LEVEL`]
+    pub significant: bool,
+}
+
+impl WelchTest {
+    /// Tests `a` against `b`, or returns `None` unless both have at least
+    /// two values and some spread
+    pub fn new(a: &[f64], b: &[f64]) -> Option<Self> {
+        if a.len() < 2 || b.len() < 2 {
+            return None;
+        }
+        let (a_summary, b_summary) = (Summary::new(a)?, Summary::new(b)?);
+        let a_variance = a_summary.std_dev.powi(2) / a.len() as f64;
+        let b_variance = b_summary.std_dev.powi(2) / b.len() as f64;
+        let variance = a_variance + b_variance;
+        if variance == 0.0 {
+            return None;
+        }
+        let t = (a_summary.mean - b_summary.mean) / variance.sqrt();
+        let degrees_of_freedom = variance.powi(2)
+            / (a_variance.powi(2) / (a.len() - 1) as f64
+                + b_variance.powi(2) / (b.len() - 1) as f64);
+        let p_value = incomplete_beta(
+            degrees_of_freedom / (degrees_of_freedom + t * t),
+            degrees_of_freedom / 2.0,
+            0.5,
+        );
+        Some(Self {
+            t,
+            degrees_of_freedom,
+            p_value,
+            significant: p_value < SIGNIFICANCE_LEVEL,
+        })
+    }
+}
+
+/// One workload of a [`Comparison`]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ComparedWorkload {
+    /// Name the workload was given, such as its command line
+    pub name: String,
+    /// The workload's runs and their statistics
+    pub measurement: RepeatedMeasurement,
+    /// Mean energy relative to the most efficient workload, net of the
+    /// baseline if [`Comparison::net_energy`] is set
+    pub relative_energy: f64,
+    /// Test of the energy against the most efficient workload, `None` for
+    /// that workload itself
+    pub test: Option<WelchTest>,
+}
+
+/// The workloads of [`BenchmarkExecutor::compare`](crate::BenchmarkExecutor::compare)
+/// side by side
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Comparison {
+    /// Index of the workload with the lowest mean energy
+    pub reference: usize,
+    /// Whether the workloads are compared by their energy net of the idle
+    /// baseline, which every run of every workload measured
+    #[serde(default)]
+    pub net_energy: bool,
+    /// The workloads in the order they were given
+    pub workloads: Vec<ComparedWorkload>,
+}
+
+impl Comparison {
+    /// Compares the energy of each workload to the most efficient one, net
+    /// of the idle baseline when every workload measured one
+    pub fn new(workloads: Vec<(String, RepeatedMeasurement)>) -> Result<Self, MeasurementError> {
+        let net_energy = workloads
+            .iter()
+            .all(|(_, measurement)| measurement.net_energy.is_some());
+        let mean = |measurement: &RepeatedMeasurement| measurement.compared_energy(net_energy).mean;
+        let reference = workloads
+            .iter()
+            .enumerate()
+            .min_by(|(_, (_, a)), (_, (_, b))| mean(a).total_cmp(&mean(b)))
+            .map(|(index, _)| index)
+            .ok_or_else(|| {
+                MeasurementError::InvalidMeasurement("no workloads to compare".to_string())
+            })?;
+        let reference_energies = workloads[reference].1.energies(net_energy);
+        let reference_mean = mean(&workloads[reference].1);
+        // Net energy can be zero, which is as good as the reference
+        let relative = |mean: f64| match mean == reference_mean {
+            true => 1.0,
+            false => mean / reference_mean,
+        };
+        let workloads = workloads
+            .into_iter()
+            .enumerate()
+            .map(|(index, (name, measurement))| ComparedWorkload {
+                relative_energy: relative(mean(&measurement)),
+                test: (index != reference)
+                    .then(|| WelchTest::new(&measurement.energies(net_energy), &reference_energies))
+                    .flatten(),
+                name,
+                measurement,
+            })
+            .collect();
+        Ok(Self {
+            reference,
+            net_energy,
+            workloads,
+        })
+    }
+}
+
+fn energy_joules(run: &EnergyMeasurement) -> f64 {
+    run.total_energy.get::<joule>()
+}
+
+/// Two-sided 95% critical value of Student's t distribution, rounding the
+/// degrees of freedom down to the table
+fn t_critical(degrees_of_freedom: usize) -> f64 {
+    match degrees_of_freedom {
+        0 => 0.0,
+        1..=30 => T_95[degrees_of_freedom - 1],
+        31..=40 => 2.042,
+        41..=60 => 2.021,
+        61..=120 => 2.000,
+        _ => 1.980,
+    }
+}
+
+/// Natural logarithm of the gamma function for `x >= 0.5`, from the
+/// Lanczos approximation with g = 7
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    let x = x - 1.0;
+    let sum = COEFFICIENTS[1..]
+        .iter()
+        .enumerate()
+        .fold(COEFFICIENTS[0], |sum, (i, c)| {
+            sum + c / (x + i as f64 + 1.0)
+        });
+    let t = x + 7.5;
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+/// Regularized incomplete beta function I_x(a, b)
+fn incomplete_beta(x: f64, a: f64, b: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    // The continued fraction converges quickly on this side of the mean
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_fraction(x, a, b) / a
+    } else {
+        1.0 - front * beta_fraction(1.0 - x, b, a) / b
+    }
+}
+
+/// Continued fraction of the incomplete beta function, by the modified
+/// Lentz method
+fn beta_fraction(x: f64, a: f64, b: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let clamp = |v: f64| if v.abs() < TINY { TINY } else { v };
+    let mut c = 1.0;
+    let mut d = 1.0 / clamp(1.0 - (a + b) * x / (a + 1.0));
+    let mut fraction = d;
+    for m in 1..=200 {
+        let m = m as f64;
+        let even = m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m));
+        d = 1.0 / clamp(1.0 + even * d);
+        c = clamp(1.0 + even / c);
+        fraction *= d * c;
+        let odd = -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0));
+        d = 1.0 / clamp(1.0 + odd * d);
+        c = clamp(1.0 + odd / c);
+        let delta = d * c;
+        fraction *= delta;
+        if (delta - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+    fraction
+}
+
+fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let middle = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[middle - 1] + sorted[middle]) / 2.0
+    } else {
+        sorted[middle]
+    }
+}
+
+/// Indices of the values whose modified z-score, based on the median
+/// absolute deviation, marks them as outliers
+pub(crate) fn outliers(values: &[f64]) -> Vec<usize> {
+    if values.len() < 3 {
+        return Vec::new();
+    }
+    let center = median(values);
+    let deviations: Vec<f64> = values.iter().map(|v| (v - center).abs()).collect();
+    let mad = median(&deviations);
+    if mad == 0.0 {
+        return Vec::new();
+    }
+    deviations
+        .iter()
+        .enumerate()
+        .filter(|(_, deviation)| 0.6745 * *deviation / mad > OUTLIER_Z_SCORE)
+        .map(|(index, _)| index)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let summary = Summary::new(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]).unwrap();
+        assert_eq!(summary.mean, 5.0);
+        assert_eq!(summary.median, 4.5);
+        assert_eq!((summary.min, summary.max), (2.0, 9.0));
+        assert!((summary.std_dev - 2.138).abs() < 1e-3);
+        // 2.365 * 2.138 / sqrt(8)
+        assert!((summary.ci_high - 6.788).abs() < 1e-3);
+        assert!((summary.ci_low - 3.212).abs() < 1e-3);
+
+        let single = Summary::new(&[3.0]).unwrap();
+        assert_eq!(
+            (single.std_dev, single.ci_low, single.ci_high),
+            (0.0, 3.0, 3.0)
+        );
+        assert_eq!(Summary::new(&[]), None);
+    }
+
+    #[test]
+    fn test_welch_test() {
+        // Welch's first example (Biometrika, 1947) as reproduced on Wikipedia
+        let a = [
+            27.5, 21.0, 19.0, 23.6, 17.0, 17.9, 16.9, 20.1, 21.9, 22.6, 23.1, 19.6, 19.0, 21.7,
+            21.4,
+        ];
+        let b = [
+            27.1, 22.0, 20.8, 23.4, 23.4, 23.5, 25.8, 22.0, 24.8, 20.2, 21.9, 22.1, 22.9, 20.5,
+            24.4,
+        ];
+        let test = WelchTest::new(&a, &b).unwrap();
+        assert!((test.t + 2.46).abs() < 0.005);
+        assert!((test.degrees_of_freedom - 24.99).abs() < 0.005);
+        assert!((test.p_value - 0.021).abs() < 0.0005);
+        assert!(test.significant);
+
+        // t = 2 with 10 degrees of freedom
+        assert!((incomplete_beta(10.0 / 14.0, 5.0, 0.5) - 0.07339).abs() < 1e-5);
+        assert_eq!(WelchTest::new(&[1.0, 1.0], &[2.0, 2.0]), None);
+        assert_eq!(WelchTest::new(&[1.0], &[2.0, 3.0]), None);
+    }
+
+    #[test]
+    fn test_outliers() {
+        assert_eq!(outliers(&[10.0, 10.2, 9.9, 10.1, 25.0, 10.0]), [4]);
+        assert!(outliers(&[10.0, 10.2, 9.9, 10.1, 10.3]).is_empty());
+        // Identical runs have no spread to compare against
+        assert!(outliers(&[1.0, 1.0, 1.0, 5.0]).is_empty());
+    }
+}
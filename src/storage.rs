@@ -0,0 +1,528 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use uom::si::energy::joule;
+use uom::si::f64::Energy;
+
+use crate::cgroup::read_io_bytes;
+use crate::procfs::{collect_descendants, ProcessScan};
+use crate::{AttributionMode, MeasurementConfig, MeasurementError, WorkloadProcesses};
+
+/// Energy per GB transferred to or from storage devices
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct StorageCoefficients {
+    /// Joules per GB for solid state drives
+    pub ssd_joules_per_gb: f64,
+    /// Joules per GB for rotational hard drives
+    pub hdd_joules_per_gb: f64,
+}
+
+impl Default for StorageCoefficients {
+    /// About 3 W at 500 MB/s for an SATA SSD and 7 W at 150 MB/s for a
+    /// hard drive
+    fn default() -> Self {
+        Self {
+            ssd_joules_per_gb: 6.0,
+            hdd_joules_per_gb: 47.0,
+        }
+    }
+}
+
+/// Whose storage I/O is counted
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum StorageScope {
+    /// `/proc/<pid>/io` of the workload processes
+    ProcessTree,
+    /// `io.stat` of the cgroup the energy is attributed to
+    Cgroup,
+    /// `/proc/diskstats` of all physical disks
+    System,
+}
+
+impl Display for StorageScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageScope::ProcessTree => write!(f, "process tree"),
+            StorageScope::Cgroup => write!(f, "cgroup"),
+            StorageScope::System => write!(f, "system"),
+        }
+    }
+}
+
+/// Data read from and written to storage during the measurement and its
+/// estimated energy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageEnergy {
+    /// Bytes read from storage devices
+    pub read_bytes: u64,
+    /// Bytes written to storage devices
+    pub written_bytes: u64,
+    /// Fraction of the I/O assumed to hit rotational drives
+    pub rotational_fraction: f64,
+    /// Estimated energy of the I/O
+    pub energy: Energy,
+    /// Whose I/O the byte counts cover
+    pub scope: StorageScope,
+}
+
+/// A physical disk from `/sys/block`
+#[derive(Debug, Clone, PartialEq)]
+struct Disk {
+    name: String,
+    rotational: bool,
+}
+
+/// Counts storage I/O over a measurement
+pub(crate) struct StorageCounter {
+    root: PathBuf,
+    scope: StorageScope,
+    cgroup: Option<PathBuf>,
+    pid: u32,
+    processes: WorkloadProcesses,
+    coefficients: StorageCoefficients,
+    disks: Vec<Disk>,
+    /// Read and written bytes per disk at the start
+    start_disks: HashMap<String, (u64, u64)>,
+    start: (u64, u64),
+    last: (u64, u64),
+    /// I/O of each thread of `pid` when last seen
+    threads: HashMap<u32, (u64, u64)>,
+    /// I/O of the threads of `pid` that exited, as last seen
+    exited_threads: (u64, u64),
+    /// Why the workload is followed differently than attributed
+    warning: Option<String>,
+}
+
+impl StorageCounter {
+    /// Starts counting when `config.storage` is set, following the same
+    /// workload as `config.attribution`
+    pub(crate) fn open(config: &MeasurementConfig) -> Result<Option<Self>, MeasurementError> {
+        let Some(coefficients) = config.storage else {
+            return Ok(None);
+        };
+        let (scope, cgroup) = match &config.attribution {
+            AttributionMode::Off => (StorageScope::System, None),
+            // io.stat is missing without the I/O controller
+            AttributionMode::Cgroup(path) if read_io_bytes(path).is_some() => {
+                (StorageScope::Cgroup, Some(path.clone()))
+            }
+            _ => (StorageScope::ProcessTree, None),
+        };
+        let warning = match &config.attribution {
+            AttributionMode::Cgroup(path) if cgroup.is_none() => Some(format!(
+                "the io controller is not enabled for {}, so storage I/O is read by process tree",
+                path.display()
+            )),
+            _ => None,
+        };
+        let mut counter = Self::new(
+            &config.fs_root,
+            scope,
+            cgroup,
+            std::process::id(),
+            config.workload_processes,
+            coefficients,
+        )?;
+        counter.warning = warning;
+        Ok(Some(counter))
+    }
+
+    fn new(
+        root: &Path,
+        scope: StorageScope,
+        cgroup: Option<PathBuf>,
+        pid: u32,
+        processes: WorkloadProcesses,
+        coefficients: StorageCoefficients,
+    ) -> Result<Self, MeasurementError> {
+        let start_disks = match scope {
+            StorageScope::System => read_diskstats(root)?,
+            // Only used to weigh the disk types otherwise
+            _ => read_diskstats(root).unwrap_or_default(),
+        };
+        let mut counter = Self {
+            root: root.to_path_buf(),
+            scope,
+            cgroup,
+            pid,
+            processes,
+            coefficients,
+            disks: read_disks(root),
+            start_disks,
+            start: (0, 0),
+            last: (0, 0),
+            threads: HashMap::new(),
+            exited_threads: (0, 0),
+            warning: None,
+        };
+        counter.start = counter.read_workload(&ProcessScan::default())?;
+        counter.last = counter.start;
+        Ok(counter)
+    }
+
+    /// Updates the I/O of the process tree, whose exited members only
+    /// show up in their parent once they are reaped, finding processes in
+    /// `scan`
+    pub(crate) fn sample(&mut self, scan: &ProcessScan) -> Result<(), MeasurementError> {
+        if self.scope == StorageScope::ProcessTree {
+            let (read, written) = self.read_workload(scan)?;
+            self.last = (self.last.0.max(read), self.last.1.max(written));
+        }
+        Ok(())
+    }
+
+    fn read_workload(&mut self, scan: &ProcessScan) -> Result<(u64, u64), MeasurementError> {
+        match self.scope {
+            StorageScope::ProcessTree => {
+                let mut members = vec![self.pid];
+                collect_descendants(scan.processes(&self.root)?, &mut members);
+                let (read, written) = members
+                    .into_iter()
+                    // Processes may exit after the tree was read
+                    .filter_map(|pid| read_process_io(&self.root, pid).ok())
+                    .fold((0, 0), |total, io| (total.0 + io.0, total.1 + io.1));
+                if self.processes == WorkloadProcesses::Current {
+                    return Ok((read, written));
+                }
+                // The I/O of reaped children is added to that of the root,
+                // so only the root's own threads are left out
+                let (own_read, own_written) = self.read_own_io();
+                Ok((
+                    read.saturating_sub(own_read),
+                    written.saturating_sub(own_written),
+                ))
+            }
+            StorageScope::Cgroup => self
+                .cgroup
+                .as_deref()
+                .and_then(read_io_bytes)
+                .ok_or_else(|| MeasurementError::InvalidMeasurement("no io.stat".to_string())),
+            StorageScope::System => Ok(self
+                .disk_deltas()?
+                .values()
+                .fold((0, 0), |total, io| (total.0 + io.0, total.1 + io.1))),
+        }
+    }
+
+    /// I/O of the threads of `pid`, including those that exited since the
+    /// start
+    ///
+    /// Exited threads also stay in the I/O of `pid`, with what they did
+    /// after they were last seen counting towards the workload.
+    fn read_own_io(&mut self) -> (u64, u64) {
+        let threads = read_thread_io(&self.root, self.pid);
+        for (tid, io) in &self.threads {
+            if !threads.contains_key(tid) {
+                self.exited_threads.0 += io.0;
+                self.exited_threads.1 += io.1;
+            }
+        }
+        self.threads = threads;
+        self.threads
+            .values()
+            .fold(self.exited_threads, |total, io| {
+                (total.0 + io.0, total.1 + io.1)
+            })
+    }
+
+    /// Bytes read and written per physical disk since the start
+    fn disk_deltas(&self) -> Result<HashMap<String, (u64, u64)>, MeasurementError> {
+        let now = read_diskstats(&self.root)?;
+        Ok(self
+            .disks
+            .iter()
+            .filter_map(|disk| {
+                let (read, written) = now.get(&disk.name)?;
+                let (start_read, start_written) = self
+                    .start_disks
+                    .get(&disk.name)
+                    .copied()
+                    .unwrap_or_default();
+                Some((
+                    disk.name.clone(),
+                    (
+                        read.saturating_sub(start_read),
+                        written.saturating_sub(start_written),
+                    ),
+                ))
+            })
+            .collect())
+    }
+
+    /// Why storage I/O is not read from the cgroup the energy is
+    /// attributed by
+    pub(crate) fn warning(&self) -> Option<&str> {
+        self.warning.as_deref()
+    }
+
+    /// The I/O since the start and its energy
+    pub(crate) fn finish(&mut self) -> Result<StorageEnergy, MeasurementError> {
+        let scan = ProcessScan::default();
+        self.sample(&scan)?;
+        let (read_bytes, written_bytes) = match self.scope {
+            StorageScope::ProcessTree => (self.last.0 - self.start.0, self.last.1 - self.start.1),
+            _ => {
+                let (read, written) = self.read_workload(&scan)?;
+                (
+                    read.saturating_sub(self.start.0),
+                    written.saturating_sub(self.start.1),
+                )
+            }
+        };
+
+        // The workload's share of each disk is unknown, so the disk types
+        // are weighed by the system-wide I/O, or by count without any
+        let deltas = self.disk_deltas().unwrap_or_default();
+        let bytes = |disk: &Disk| deltas.get(&disk.name).map_or(0, |io| io.0 + io.1);
+        let total: u64 = self.disks.iter().map(bytes).sum();
+        let rotational: u64 = self.disks.iter().filter(|d| d.rotational).map(bytes).sum();
+        let rotational_fraction = if total > 0 {
+            rotational as f64 / total as f64
+        } else if !self.disks.is_empty() {
+            let count = self.disks.iter().filter(|d| d.rotational).count();
+            count as f64 / self.disks.len() as f64
+        } else {
+            0.0
+        };
+
+        let joules_per_gb = rotational_fraction * self.coefficients.hdd_joules_per_gb
+            + (1.0 - rotational_fraction) * self.coefficients.ssd_joules_per_gb;
+        let gigabytes = (read_bytes + written_bytes) as f64 / 1e9;
+        Ok(StorageEnergy {
+            read_bytes,
+            written_bytes,
+            rotational_fraction,
+            energy: Energy::new::<joule>(gigabytes * joules_per_gb),
+            scope: self.scope,
+        })
+    }
+}
+
+/// Reads `read_bytes` and `write_bytes` from `proc/<pid>/io` below `root`
+fn read_process_io(root: &Path, pid: u32) -> Result<(u64, u64), MeasurementError> {
+    read_io(&root.join("proc").join(pid.to_string()).join("io"))
+}
+
+/// Reads the I/O of each live thread of `pid` from `proc/<pid>/task`
+/// below `root`
+fn read_thread_io(root: &Path, pid: u32) -> HashMap<u32, (u64, u64)> {
+    let Ok(tasks) = fs::read_dir(root.join("proc").join(pid.to_string()).join("task")) else {
+        return HashMap::new();
+    };
+    tasks
+        .flatten()
+        .filter_map(|task| {
+            let tid = task.file_name().to_str()?.parse().ok()?;
+            // Threads may exit while the directory is being read
+            Some((tid, read_io(&task.path().join("io")).ok()?))
+        })
+        .collect()
+}
+
+fn read_io(path: &Path) -> Result<(u64, u64), MeasurementError> {
+    let io = fs::read_to_string(path)?;
+    let field = |name: &str| {
+        io.lines()
+            .find_map(|line| line.strip_prefix(name))
+            .and_then(|value| value.trim().parse().ok())
+            .ok_or_else(|| {
+                MeasurementError::InvalidMeasurement(format!("Failed to parse {}", path.display()))
+            })
+    };
+    Ok((field("read_bytes:")?, field("write_bytes:")?))
+}
+
+/// Lists the physical disks in `sys/block` below `root`; partitions,
+/// loop, RAM and device-mapper devices have no `device` link
+fn read_disks(root: &Path) -> Vec<Disk> {
+    let Ok(entries) = fs::read_dir(root.join("sys/block")) else {
+        return Vec::new();
+    };
+    let mut disks: Vec<Disk> = entries
+        .flatten()
+        .filter(|entry| entry.path().join("device").exists())
+        .filter_map(|entry| {
+            let rotational = fs::read_to_string(entry.path().join("queue/rotational")).ok()?;
+            Some(Disk {
+                name: entry.file_name().to_str()?.to_string(),
+                rotational: rotational.trim() == "1",
+            })
+        })
+        .collect();
+    disks.sort_by(|a, b| a.name.cmp(&b.name));
+    disks
+}
+
+/// Reads bytes read and written per device from `proc/diskstats`
+fn read_diskstats(root: &Path) -> Result<HashMap<String, (u64, u64)>, MeasurementError> {
+    let stats = fs::read_to_string(root.join("proc/diskstats"))?;
+    let mut devices = HashMap::new();
+    for line in stats.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 10 {
+            continue;
+        }
+        // Sectors are always 512 bytes here, whatever the device uses
+        let sectors = |index: usize| fields[index].parse::<u64>().unwrap_or(0) * 512;
+        devices.insert(fields[2].to_string(), (sectors(5), sectors(9)));
+    }
+    Ok(devices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::FakeRoot;
+
+    fn diskstats(nvme: (u64, u64), sda: (u64, u64)) -> String {
+        format!(
+            " 259       0 nvme0n1 10 0 {} 5 20 0 {} 9 0 10 14 0 0 0 0\n \
+             259       1 nvme0n1p1 10 0 {} 5 20 0 {} 9 0 10 14 0 0 0 0\n   \
+             8       0 sda 10 0 {} 5 20 0 {} 9 0 10 14 0 0 0 0\n   \
+             7       0 loop0 10 0 99999 5 0 0 0 0 0 10 14 0 0 0 0\n",
+            nvme.0, nvme.1, nvme.0, nvme.1, sda.0, sda.1
+        )
+    }
+
+    fn disks(root: &FakeRoot) {
+        root.file("sys/block/nvme0n1/device/vendor", "0x144d\n")
+            .file("sys/block/nvme0n1/queue/rotational", "0\n")
+            .file("sys/block/sda/device/vendor", "ATA\n")
+            .file("sys/block/sda/queue/rotational", "1\n")
+            .file("sys/block/loop0/queue/rotational", "1\n");
+    }
+
+    #[test]
+    fn test_system_storage() {
+        let root = FakeRoot::new("storage-system");
+        disks(&root);
+        root.file("proc/diskstats", &diskstats((1000, 2000), (0, 0)));
+        let mut counter = StorageCounter::new(
+            root.path(),
+            StorageScope::System,
+            None,
+            1,
+            WorkloadProcesses::Current,
+            StorageCoefficients::default(),
+        )
+        .unwrap();
+        assert_eq!(counter.disks.len(), 2);
+
+        // 3 GB on the SSD and 1 GB on the hard drive, in 512 byte sectors
+        root.file(
+            "proc/diskstats",
+            &diskstats((1000 + 3_906_250, 2000 + 1_953_125), (1_953_125, 0)),
+        );
+        let storage = counter.finish().unwrap();
+        assert_eq!(storage.read_bytes, 3_000_000_000);
+        assert_eq!(storage.written_bytes, 1_000_000_000);
+        assert_eq!(storage.rotational_fraction, 0.25);
+        assert_eq!(storage.energy.get::<joule>(), 3.0 * 6.0 + 47.0);
+    }
+
+    fn io(read: u64, written: u64) -> String {
+        format!(
+            "rchar: 1\nwchar: 1\nsyscr: 1\nsyscw: 1\n\
+             read_bytes: {read}\nwrite_bytes: {written}\ncancelled_write_bytes: 0\n"
+        )
+    }
+
+    fn process(root: &FakeRoot, pid: u32, ppid: u32, read: u64, written: u64) {
+        root.file(
+            &format!("proc/{pid}/stat"),
+            &format!("{pid} (job) S {ppid} 1 1 0 -1 0 0 0 0 0 0 0 0 0 20 0 1 0 1 0 0"),
+        )
+        .file(&format!("proc/{pid}/io"), &io(read, written));
+    }
+
+    #[test]
+    fn test_process_tree_storage() {
+        let root = FakeRoot::new("storage-tree");
+        disks(&root);
+        let process = |pid, ppid, read, written| process(&root, pid, ppid, read, written);
+        process(100, 1, 0, 0);
+        process(200, 100, 0, 0);
+        process(300, 1, 5000, 5000);
+        let mut counter = StorageCounter::new(
+            root.path(),
+            StorageScope::ProcessTree,
+            None,
+            100,
+            WorkloadProcesses::Current,
+            StorageCoefficients::default(),
+        )
+        .unwrap();
+
+        process(200, 100, 1_000_000_000, 0);
+        counter.sample(&ProcessScan::default()).unwrap();
+        // 200 exits and is reaped, handing its I/O to 100
+        fs::remove_dir_all(root.path().join("proc/200")).unwrap();
+        process(100, 1, 1_000_000_000, 0);
+        let storage = counter.finish().unwrap();
+        assert_eq!(
+            (storage.read_bytes, storage.written_bytes),
+            (1_000_000_000, 0)
+        );
+        // Without diskstats the disks are weighed by count
+        assert_eq!(storage.rotational_fraction, 0.5);
+        assert_eq!(storage.energy.get::<joule>(), 26.5);
+    }
+
+    #[test]
+    fn test_children_storage() {
+        let root = FakeRoot::new("storage-children");
+        process(&root, 100, 1, 1000, 1000);
+        process(&root, 200, 100, 0, 0);
+        root.file("proc/100/task/100/io", &io(1000, 1000));
+        let mut counter = StorageCounter::new(
+            root.path(),
+            StorageScope::ProcessTree,
+            None,
+            100,
+            WorkloadProcesses::Children,
+            StorageCoefficients::default(),
+        )
+        .unwrap();
+
+        // The sampling process writes as well, and 200 is reaped by it
+        fs::remove_dir_all(root.path().join("proc/200")).unwrap();
+        process(&root, 100, 1, 1000 + 4000, 1000 + 500 + 3000);
+        root.file("proc/100/task/100/io", &io(1000, 1500));
+        let storage = counter.finish().unwrap();
+        assert_eq!((storage.read_bytes, storage.written_bytes), (4000, 3000));
+    }
+
+    #[test]
+    fn test_children_storage_exited_thread() {
+        let root = FakeRoot::new("storage-thread");
+        process(&root, 100, 1, 1000, 1000);
+        process(&root, 200, 100, 0, 0);
+        root.file("proc/100/task/100/io", &io(600, 600))
+            .file("proc/100/task/101/io", &io(400, 400));
+        let mut counter = StorageCounter::new(
+            root.path(),
+            StorageScope::ProcessTree,
+            None,
+            100,
+            WorkloadProcesses::Children,
+            StorageCoefficients::default(),
+        )
+        .unwrap();
+
+        // A thread of the sampling process writes, then exits, leaving its
+        // I/O in that of the process
+        root.file("proc/100/task/101/io", &io(400, 900));
+        process(&root, 100, 1, 1000, 1500);
+        counter.sample(&ProcessScan::default()).unwrap();
+        fs::remove_dir_all(root.path().join("proc/100/task/101")).unwrap();
+        fs::remove_dir_all(root.path().join("proc/200")).unwrap();
+        process(&root, 100, 1, 1000 + 4000, 1500 + 3000);
+        let storage = counter.finish().unwrap();
+        assert_eq!((storage.read_bytes, storage.written_bytes), (4000, 3000));
+    }
+}